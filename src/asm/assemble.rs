@@ -0,0 +1,673 @@
+//! Running the whole assembler over some input.
+
+use std::{
+    cell::RefCell,
+    io::Write,
+    ops::Range,
+    panic::{self, AssertUnwindSafe},
+    rc::Rc,
+    time::Instant,
+};
+
+use crate::asm::{
+    error::Reporter,
+    language::{Lexer, Parser, Token, Tokenizer, Warning, WarningKind},
+    options::Options,
+    sections::Sections,
+    symbols::Symbols,
+    Fstack, Storage,
+};
+
+/// A writer whose contents can still be read after it has been handed to a [`Reporter`].
+#[derive(Debug, Clone, Default)]
+pub struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuf {
+    pub fn contents(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).expect("Diagnostics should be valid UTF-8")
+    }
+}
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Assembles everything reachable from the file stack's root file into `sections` and `symbols`.
+/// What `PRINT` and friends print is written to `output`.
+pub fn run<'fstack>(
+    fstack: &'fstack Fstack,
+    reporter: &RefCell<Reporter>,
+    options: &RefCell<Options>,
+    sections: &RefCell<Sections<'fstack>>,
+    symbols: &RefCell<Symbols<'fstack>>,
+    output: &RefCell<dyn Write>,
+) {
+    let lexer = RefCell::new(Lexer::new());
+    let macro_args = RefCell::new(Vec::new());
+
+    if let Err(error) = Parser::new().parse(
+        fstack,
+        &lexer,
+        &macro_args,
+        sections,
+        symbols,
+        reporter,
+        options,
+        output,
+        Tokenizer::new(fstack, &lexer, &macro_args, reporter, symbols),
+    ) {
+        reporter.borrow_mut().report_fatal_error(fstack, error);
+    }
+    for err in sections.borrow_mut().end_of_input() {
+        reporter.borrow_mut().report_error(fstack, err.into());
+    }
+    for err in symbols.borrow_mut().end_of_input(&sections.borrow()) {
+        reporter.borrow_mut().report_error(fstack, err.into());
+    }
+
+    let mut reporter = reporter.borrow_mut();
+    for (name, (begin, end)) in symbols.borrow().unused_labels() {
+        reporter.warn(
+            fstack,
+            Warning {
+                begin: begin.clone(),
+                end: end.clone(),
+                kind: WarningKind::Unused(name.into()),
+            },
+        );
+    }
+    reporter.report_duplicates(fstack);
+    reporter.report_suppressed(fstack);
+}
+
+/// Runs one phase of assembly, and logs how long it took if given somewhere to.
+pub fn timed<T, W: Write, F: FnOnce() -> T>(log: Option<W>, phase: &str, f: F) -> T {
+    let start = Instant::now();
+    let ret = f();
+    if let Some(mut log) = log {
+        // This is purely informational, so failing to print it is not worth aborting over.
+        let _ = writeln!(log, "{phase} took {:.3?}", start.elapsed());
+    }
+    ret
+}
+
+/// What [`assemble_str`] produced.
+#[derive(Debug)]
+pub struct AssembleResult<T> {
+    /// What `inspect` returned, or `None` if any errors were reported.
+    pub output: Option<T>,
+    /// Every diagnostic that was reported, each rendered on its own, without colours.
+    pub diagnostics: Vec<String>,
+    /// Everything that `PRINT` and friends printed.
+    pub printed: String,
+}
+
+/// Assembles a source string without touching the filesystem; `INCLUDE`d files are obtained from
+/// `resolver` instead.
+///
+/// Sections and symbols cannot outlive the assembly's file stack, so they are handed to `inspect`,
+/// which is only called if assembly succeeded.
+pub fn assemble_str<R, I, T>(
+    source: &str,
+    options: &Options,
+    resolver: R,
+    inspect: I,
+) -> AssembleResult<T>
+where
+    R: Fn(&str) -> Option<String> + 'static,
+    I: FnOnce(&Sections, &Symbols) -> T,
+{
+    assemble_collecting(source, options, resolver, inspect, false)
+}
+
+/// Like [`assemble_str`], but never panics, however malformed the input: should the assembler
+/// run into a bug or an unimplemented feature, it is reported as one last error diagnostic.
+pub fn try_assemble<R, I, T>(
+    source: &str,
+    options: &Options,
+    resolver: R,
+    inspect: I,
+) -> AssembleResult<T>
+where
+    R: Fn(&str) -> Option<String> + 'static,
+    I: FnOnce(&Sections, &Symbols) -> T,
+{
+    assemble_collecting(source, options, resolver, inspect, true)
+}
+
+fn assemble_collecting<R, I, T>(
+    source: &str,
+    options: &Options,
+    resolver: R,
+    inspect: I,
+    catch_panics: bool,
+) -> AssembleResult<T>
+where
+    R: Fn(&str) -> Option<String> + 'static,
+    I: FnOnce(&Sections, &Symbols) -> T,
+{
+    let reporter = RefCell::new(Reporter::collecting());
+    let options = RefCell::new(options.clone());
+
+    let root_file = Storage::from_readable("<string>".to_string().into(), source.as_bytes())
+        .expect("Reading from a slice cannot fail");
+    let fstack = Fstack::with_resolver(Rc::new(root_file), resolver);
+    let sections = RefCell::new(Sections::new());
+    let symbols = RefCell::new(Symbols::new());
+    let printed = RefCell::new(Vec::new());
+    let assemble = || run(&fstack, &reporter, &options, &sections, &symbols, &printed);
+    let panic = if catch_panics {
+        // Nothing assembled is looked at after a panic, only the diagnostics reported until then.
+        panic::catch_unwind(AssertUnwindSafe(assemble)).err()
+    } else {
+        assemble();
+        None
+    };
+
+    let mut reporter = reporter.into_inner();
+    let mut diagnostics = reporter.take_collected();
+    let output = match panic {
+        Some(payload) => {
+            let msg = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown panic");
+            diagnostics.push(format!("error: internal error: {msg}\n"));
+            None
+        }
+        None => (reporter.nb_errors() == 0).then(|| inspect(&sections.borrow(), &symbols.borrow())),
+    };
+    AssembleResult {
+        output,
+        diagnostics,
+        printed: String::from_utf8_lossy(&printed.into_inner()).into_owned(),
+    }
+}
+
+/// Splits a source string into tokens without assembling it, e.g. for syntax highlighting.
+/// Each token comes with the range of bytes it spans in `source`.
+///
+/// Since nothing gets defined, neither macro args nor `EQUS` are expanded: macro args are skipped,
+/// like any characters that cannot be lexed. Only the modes that the lexer switches to by itself
+/// apply (e.g. `OPT`'s arguments are read raw), so macro arguments and bodies are read as regular
+/// tokens. Like for the parser, the end of input yields one last, empty, newline token.
+pub fn tokenize(source: &str) -> impl Iterator<Item = (Token, Range<usize>)> {
+    let reporter = RefCell::new(Reporter::collecting());
+    let root_file = Storage::from_readable("<string>".to_string().into(), source.as_bytes())
+        .expect("Reading from a slice cannot fail");
+    let fstack = Fstack::new(Rc::new(root_file), vec![]);
+    let lexer = RefCell::new(Lexer::new());
+    let macro_args = RefCell::new(Vec::new());
+    let symbols = RefCell::new(Symbols::new());
+
+    // The tokens cannot outlive the file stack, so they must all be read right away.
+    let tokens: Vec<_> = Tokenizer::new(&fstack, &lexer, &macro_args, &reporter, &symbols)
+        .filter_map(Result::ok)
+        .filter(|(_, token, _)| !matches!(token, Token::LookaheadHack))
+        .map(|(begin, token, end)| (token, begin.offset()..end.offset()))
+        .collect();
+    tokens.into_iter()
+}
+
+#[cfg(test)]
+mod tests {
+    use codespan_reporting::term::termcolor::NoColor;
+
+    use super::*;
+    use crate::asm::input::Encoding;
+
+    #[test]
+    fn in_memory_include() {
+        let resolver = |path: &str| (path == "pad.inc").then(|| "OPT p42\nds 2\n".to_string());
+        let result = assemble_str(
+            "SECTION \"a\", ROM0\nds 1\nINCLUDE \"pad.inc\"\nds 1\n",
+            &Options::default(),
+            resolver,
+            |sections, _| sections.active_section().unwrap().data().to_vec(),
+        );
+        assert_eq!(
+            result.output.as_deref(),
+            Some(&[0x00, 0x42, 0x42, 0x42][..])
+        );
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+
+        let result = assemble_str(
+            "INCLUDE \"missing.inc\"\n",
+            &Options::default(),
+            resolver,
+            |_, _| (),
+        );
+        assert_eq!(result.output, None);
+        assert_eq!(result.diagnostics.len(), 1);
+        assert!(
+            result.diagnostics[0].starts_with("error: "),
+            "{:?}",
+            result.diagnostics
+        );
+    }
+
+    #[test]
+    fn separate_diagnostics() {
+        let result = assemble_str(
+            "SECTION \"a\", ROM0\ndb 1, 2\nOPT q\ndw $1234\nOPT z\n",
+            &Options::default(),
+            |_| None,
+            |sections, _| sections.active_section().unwrap().data().to_vec(),
+        );
+        assert_eq!(result.output.as_deref(), Some(&[1, 2, 0x34, 0x12][..]));
+        assert_eq!(
+            result.diagnostics,
+            [
+                "warning[-Winvalid-opt]: Unknown option 'q'\n  ┌─ <string>:3:5\n  │\n3 │ OPT q\n  │     ^\n\n",
+                "warning[-Winvalid-opt]: Unknown option 'z'\n  ┌─ <string>:5:5\n  │\n5 │ OPT z\n  │     ^\n\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn timing() {
+        let mut log = Vec::new();
+        assert_eq!(timed(Some(&mut log), "Reading input", || 42), 42);
+        timed(Some(&mut log), "Assembling", || ());
+        timed(None::<&mut Vec<u8>>, "Writing output", || ());
+
+        let log = String::from_utf8(log).unwrap();
+        let phases: Vec<_> = log
+            .lines()
+            .map(|line| line.split_once(" took ").unwrap())
+            .collect();
+        assert_eq!(phases.len(), 2, "{log}");
+        assert_eq!(phases[0].0, "Reading input");
+        assert_eq!(phases[1].0, "Assembling");
+        assert!(phases.iter().all(|(_, time)| time.ends_with('s')), "{log}");
+    }
+
+    #[test]
+    fn legacy_print() {
+        let result = assemble_str(
+            "PRINTT \"hi \"\nPRINTV 42\nPRINTI -3 * 2\nPRINTLN \"\", 1 + 1\n",
+            &Options::default(),
+            |_| None,
+            |_, _| (),
+        );
+        assert_eq!(result.printed, "hi $2A-6$2\n");
+        let directives = ["PRINTT", "PRINTV", "PRINTI"];
+        assert_eq!(result.diagnostics.len(), directives.len());
+        for (diagnostic, directive) in result.diagnostics.iter().zip(directives) {
+            assert!(
+                diagnostic
+                    .starts_with(&format!("warning[-Wobsolete]: `{directive}` is deprecated")),
+                "{diagnostic}"
+            );
+        }
+    }
+
+    #[test]
+    fn lexer_recovery() {
+        let result = try_assemble(
+            "SECTION \"a\", ROM0\ndb \"a\\nb\\\"c\\\\\", &17, %101\ndw `33\ndb 1, \\ ; cont\n 2\nMACRO m\ndb \\1 + 1\nENDM\nm 3\ndb 4 ; no newline",
+            &Options::default(),
+            |_| None,
+            |sections, _| sections.active_section().unwrap().data().to_vec(),
+        );
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(
+            result.output.as_deref(),
+            Some(&[b'a', b'\n', b'b', b'"', b'c', b'\\', 0o17, 0b101, 3, 3, 1, 2, 4, 4][..])
+        );
+
+        // These are errors in the source, not in the assembler.
+        for source in [
+            "db \\1",
+            "db \"abc",
+            "PRINTLN \"\\q\"",
+            "db 1 \\ 2",
+            "foo \\\n",
+        ] {
+            let result = try_assemble(source, &Options::default(), |_| None, |_, _| ());
+            assert_eq!(result.output, None, "{source:?}");
+            assert!(
+                result
+                    .diagnostics
+                    .iter()
+                    .all(|diagnostic| !diagnostic.contains("internal error")),
+                "{source:?}: {:?}",
+                result.diagnostics
+            );
+        }
+    }
+
+    #[test]
+    fn digit_separators() {
+        let result = assemble_str(
+            concat!(
+                "SECTION \"a\", ROM0\ndw $FF_FF, `3300_0033\ndb %1010_1010, &1_7, 1_0\n",
+                // Too many digits wrap around, like in other bases.
+                "db %1_0000_0000_0000_0000_0000_0000_0000_0010\n",
+            ),
+            &Options::default(),
+            |_| None,
+            |sections, _| sections.active_section().unwrap().data().to_vec(),
+        );
+        assert_eq!(result.diagnostics, Vec::<String>::new());
+        assert_eq!(
+            result.output.as_deref(),
+            Some(&[0xFF, 0xFF, 0xC3, 0xC3, 0b1010_1010, 0o17, 10, 2][..])
+        );
+
+        let result = assemble_str(
+            "SECTION \"a\", ROM0\ndb 1__0, $F_\n",
+            &Options::default(),
+            |_| None,
+            |_, _| (),
+        );
+        assert_eq!(
+            result.diagnostics,
+            [
+                "error: Syntax error: digit separators cannot be doubled\n  ┌─ <string>:2:5\n  │\n2 │ db 1__0, $F_\n  │     ^^\n\n",
+                "error: Syntax error: numbers cannot end with a digit separator\n  ┌─ <string>:2:12\n  │\n2 │ db 1__0, $F_\n  │            ^\n\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn string_escapes() {
+        let result = assemble_str(
+            concat!(
+                "MACRO m\n    db \\1\nENDM\n",
+                "SECTION \"a\", ROM0\ndb \"\\n\\t\\\"\\\\\", \"\\x41\\xfF\"\nds 2, \"\\x80\"\n",
+                "db STRCAT(\"\\x01\", \"\u{10FF41}\")\nm \"\\x42\"\n",
+            ),
+            &Options::default(),
+            |_| None,
+            |sections, _| sections.active_section().unwrap().data().to_vec(),
+        );
+        assert_eq!(result.diagnostics, Vec::<String>::new());
+        // Source characters are never mistaken for raw bytes, whatever their code point.
+        assert_eq!(
+            result.output.as_deref(),
+            Some(
+                &[
+                    b'\n', b'\t', b'"', b'\\', 0x41, 0xFF, 0x80, 0x00, 0x01, 0xF4, 0x8F, 0xBD,
+                    0x81, 0x42
+                ][..]
+            )
+        );
+
+        let result = assemble_str(
+            "SECTION \"a\", ROM0\ndb \"\\q\", \"\\x4g\"\n",
+            &Options::default(),
+            |_| None,
+            |_, _| (),
+        );
+        assert_eq!(
+            result.diagnostics,
+            [
+                "error: Cannot escape 'q'\n  ┌─ <string>:2:5\n  │\n2 │ db \"\\q\", \"\\x4g\"\n  │     ^\n\n",
+                "error: `\\x` must be followed by two hexadecimal digits\n  ┌─ <string>:2:11\n  │\n2 │ db \"\\q\", \"\\x4g\"\n  │           ^^^\n\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn latin1_strings() {
+        let source = &b"SECTION \"\xE9\", ROM0\ndb \"caf\xE9\\x80\"\nSECTION \"\\x80\", ROM0\n"[..];
+        let root_file =
+            Storage::from_readable_with("latin1.asm".to_string().into(), source, Encoding::Latin1)
+                .expect("Reading from a slice cannot fail");
+        let mut fstack = Fstack::new(Rc::new(root_file), vec![]);
+        fstack.set_encoding(Encoding::Latin1);
+        let buf = SharedBuf::default();
+        let reporter = RefCell::new(Reporter::with_writer(Box::new(NoColor::new(buf.clone()))));
+        let sections = RefCell::new(Sections::new());
+        run(
+            &fstack,
+            &reporter,
+            &RefCell::new(Options::default()),
+            &sections,
+            &RefCell::new(Symbols::new()),
+            &RefCell::new(std::io::sink()),
+        );
+
+        // Text is emitted in the source's encoding, and raw bytes cannot be part of a name.
+        assert_eq!(
+            buf.contents(),
+            "error: Raw bytes from `\\x` escapes cannot be used outside of data\n  ┌─ latin1.asm:3:9\n  │\n3 │ SECTION \"\\x80\", ROM0\n  │         ^^^^^^\n\n"
+        );
+        let sections = sections.borrow();
+        let section = sections.get_by_name("é").unwrap();
+        assert_eq!(section.data(), b"caf\xE9\x80");
+    }
+
+    #[test]
+    fn ldi_ldd() {
+        let modern = "SECTION \"a\", ROM0\nld [hl+], a\nld a, [hl+]\nld [hl-], a\nld a, [hl-]\n";
+        let legacy = "SECTION \"a\", ROM0\nldi [hl], a\nldi a, [hl]\nldd [hl], a\nldd a, [hl]\n";
+        let assemble = |source| {
+            assemble_str(
+                source,
+                &Options::default(),
+                |_| None,
+                |sections, _| sections.active_section().unwrap().data().to_vec(),
+            )
+        };
+
+        let result = assemble(modern);
+        assert_eq!(result.diagnostics, Vec::<String>::new());
+        assert_eq!(
+            result.output.as_deref(),
+            Some(&[0x22, 0x2A, 0x32, 0x3A][..])
+        );
+        let result = assemble(legacy);
+        assert_eq!(result.diagnostics, Vec::<String>::new());
+        assert_eq!(
+            result.output.as_deref(),
+            Some(&[0x22, 0x2A, 0x32, 0x3A][..])
+        );
+
+        // Invalid operands are only reported as such, not as a legacy spelling.
+        let result =
+            assemble("OPT Wlegacy-ld\nSECTION \"a\", ROM0\nldd a, [hl]\nld a, [hl-]\nldi b, c\n");
+        assert_eq!(
+            result.diagnostics,
+            [
+                "warning[-Wlegacy-ld]: `ldd` is a legacy spelling of `ld [hl-]`\n  ┌─ <string>:3:1\n  │\n3 │ ldd a, [hl]\n  │ ^^^^^^^^^^^\n\n",
+                "error: Invalid instruction: `ldi` only exists as `ldi [hl], a` and `ldi a, [hl]`\n  ┌─ <string>:5:1\n  │\n5 │ ldi b, c\n  │ ^^^^^^^^\n\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn loops() {
+        let mut options = Options::default();
+        options.print_radix = crate::asm::options::PrintRadix::Decimal;
+        options.max_loop_iterations = 100;
+        let result = assemble_str(
+            concat!(
+                "REPT 3\nPRINT \"a\"\nENDR\n",
+                "REPT -5\nPRINT \"b\"\nENDR\n",
+                "FOR I, 10, 0, -3\nPRINT \" \", I\nENDR\n",
+                "FOR I, 2\nPRINT \" \", I * 2\nENDR\n",
+            ),
+            &options,
+            |_| None,
+            |_, _| (),
+        );
+        assert_eq!(result.diagnostics, Vec::<String>::new());
+        assert_eq!(result.printed, "aaa 10 7 4 1 0 2");
+
+        let result = assemble_str(
+            "FOR I, 0, 1000\nPRINT \"c\"\nENDR\nREPT 100\nENDR\nFOR I, 0, 5, 0\nENDR\n",
+            &options,
+            |_| None,
+            |_, _| (),
+        );
+        assert_eq!(
+            result.diagnostics,
+            [
+                "error: This loop would run 1000 times, more than the limit of 100\n  ┌─ <string>:1:1\n  │\n1 │ FOR I, 0, 1000\n  │ ^^^^^^^^^^^^^^\n  │\n  = The limit can be changed with `--max-loop-iterations`\n\n",
+                "error: `FOR` cannot have a step of 0\n  ┌─ <string>:6:1\n  │\n6 │ FOR I, 0, 5, 0\n  │ ^^^^^^^^^^^^^^\n\n",
+            ]
+        );
+        assert_eq!(result.printed, "");
+    }
+
+    #[test]
+    fn fuzz() {
+        const FRAGMENTS: &[&str] = &[
+            "SECTION \"a\", ROM0\n",
+            "db ",
+            "dw ",
+            "ld a, ",
+            "nop",
+            "\n",
+            "Label:",
+            ".loc",
+            "(",
+            ")",
+            "+",
+            "-",
+            "*",
+            "&&",
+            "\"",
+            "\\",
+            "\\1",
+            "{",
+            "}",
+            "MACRO",
+            "ENDM",
+            "REPT 2",
+            "ENDR",
+            "IF 1",
+            "ELSE",
+            "ENDC",
+            "DEF",
+            "EQU",
+            "EQUS",
+            ",",
+            "[",
+            "]",
+            "$",
+            "%",
+            "`",
+            "&",
+            "1",
+            "x",
+            " ",
+            "\t",
+            ";",
+            "/*",
+            "*/",
+            "@",
+            "::",
+            "jr",
+            "INCLUDE",
+            "PRINTLN",
+            "OPT",
+            "ds ",
+            "LOAD",
+            "ENDL",
+            "STARTOF",
+            "hl",
+            "\\@",
+            "\\<",
+            ".5",
+            "bit ",
+            "[$ff00 + ",
+            "UNION",
+            "STRSUB(",
+            "FAIL",
+        ];
+
+        // Inputs that used to make the assembler panic.
+        const REGRESSIONS: &[&[u8]] = &[b"DEF X EQU %111111111111111111111111111111111111111\n"];
+
+        // A fixed xorshift generator, so that any failure can be reproduced.
+        let mut state = 0x1234_5678_9abc_def0_u64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let random_inputs = (0..1000).map(|_| {
+            let mut source = Vec::new();
+            for _ in 0..next() % 30 {
+                let r = next();
+                // Any byte at all, including control characters and ones that are not UTF-8.
+                if r % 2 == 0 {
+                    source.push((r >> 8) as u8);
+                } else {
+                    let fragment = FRAGMENTS[(r >> 8) as usize % FRAGMENTS.len()];
+                    source.extend_from_slice(fragment.as_bytes());
+                }
+            }
+            source
+        });
+
+        for bytes in REGRESSIONS
+            .iter()
+            .map(|bytes| bytes.to_vec())
+            .chain(random_inputs)
+        {
+            // The bytes are read as Latin-1, which accepts any of them, and as UTF-8 if they can be.
+            let latin1 = bytes.iter().copied().map(char::from).collect();
+            for source in [Some(latin1), String::from_utf8(bytes).ok()]
+                .into_iter()
+                .flatten()
+            {
+                let result = try_assemble(&source, &Options::default(), |_| None, |_, _| ());
+                if result.output.is_none() {
+                    assert!(!result.diagnostics.is_empty(), "{source:?}");
+                }
+                // Malformed input must be reported as such, not make the assembler panic.
+                for diagnostic in &result.diagnostics {
+                    assert!(
+                        !diagnostic.contains("internal error"),
+                        "{source:?}: {diagnostic}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tokens() {
+        let source = "Label: ld a, $2A ; comment\nOPT p\\1\n";
+        let tokens: Vec<_> = tokenize(source).collect();
+        let kinds: Vec<_> = tokens
+            .iter()
+            .map(|(token, span)| (token.to_string(), &source[span.clone()]))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                ("label", "Label"),
+                ("\":\"", ":"),
+                ("\"ld\"", "ld"),
+                ("\"a\"", "a"),
+                ("\",\"", ","),
+                ("number", "$2A"),
+                ("newline", "\n"),
+                ("\"opt\"", "OPT"),
+                ("string", "p\\1"),
+                ("newline", "\n"),
+                ("newline", ""),
+            ]
+            .map(|(kind, text)| (kind.to_string(), text))
+        );
+
+        // The macro arg in `OPT`'s raw argument is skipped, not expanded.
+        let Token::String(arg) = &tokens[8].0 else {
+            panic!("{:?}", tokens[8]);
+        };
+        assert_eq!(&*arg.text, "p");
+    }
+}
@@ -0,0 +1,102 @@
+use std::{io::IsTerminal, path::PathBuf};
+
+use clap::{Parser, ValueEnum};
+use codespan_reporting::term::termcolor::ColorChoice;
+
+use crate::asm::{
+    error::{DiagnosticFormat, WarningFlag},
+    input::Encoding,
+    options::{parse_number, PrintRadix},
+};
+
+/// Assembles a source file into an object file.
+#[derive(Debug, Parser)]
+#[command(name = "rgbasm", version)]
+pub struct Cli {
+    /// Adds a directory to search for `INCLUDE`d files in.
+    ///
+    /// Directories are searched in the order they are specified, after the including file's own.
+    #[arg(short = 'I', long = "include", value_name = "path")]
+    pub include_paths: Vec<PathBuf>,
+
+    /// The byte used to fill space reserved in ROM sections.
+    #[arg(short = 'p', long = "pad-value", value_name = "value", value_parser = parse_number::<u8>)]
+    pub pad_byte: Option<u8>,
+
+    /// How deeply files, macros, and loops may be nested.
+    #[arg(short = 'r', long = "recursion-depth", value_name = "depth", value_parser = parse_number::<usize>)]
+    pub max_recursion_depth: Option<usize>,
+
+    /// How many times a single `REPT` or `FOR` may iterate.
+    #[arg(long, value_name = "count", value_parser = parse_number::<u32>)]
+    pub max_loop_iterations: Option<u32>,
+
+    /// The base `PRINT` uses for numbers that are not given a format: 16 (the default) or 10.
+    #[arg(long, value_name = "radix")]
+    pub print_radix: Option<PrintRadix>,
+
+    /// Stops assembling after this many errors; 0 means never.
+    #[arg(short = 'X', long, value_name = "count", value_parser = parse_number::<usize>)]
+    pub max_errors: Option<usize>,
+
+    /// How source files are decoded.
+    #[arg(long, value_name = "encoding", default_value = "utf-8")]
+    pub input_encoding: Encoding,
+
+    /// Whether to colour diagnostics.
+    #[arg(long, value_name = "when", default_value = "auto")]
+    pub color: ColorWhen,
+
+    /// How many columns tabs are expanded to when printing source code in diagnostics.
+    #[arg(long, value_name = "width", value_parser = parse_number::<usize>)]
+    pub tab_width: Option<usize>,
+
+    /// How to print diagnostics.
+    #[arg(long, value_name = "format", default_value = "human")]
+    pub error_format: DiagnosticFormat,
+
+    /// Enables (`-W<warning>`) or disables (`-Wno-<warning>`) a warning or group of warnings.
+    ///
+    /// `-Werror` turns all warnings into errors, and `-Werror=<warning>` only some.
+    #[arg(short = 'W', value_name = "warning")]
+    pub warnings: Vec<WarningFlag>,
+
+    /// Enables extra warnings about stylistic or risky patterns.
+    ///
+    /// These can still be disabled individually.
+    #[arg(long)]
+    pub pedantic: bool,
+
+    /// Reports diagnostics identical to an earlier one only once, with how many times they occurred.
+    #[arg(long)]
+    pub dedup_diagnostics: bool,
+
+    /// Writes every section's name, kind, attributes, and size to this file as JSON after assembly.
+    #[arg(long, value_name = "path")]
+    pub section_json: Option<PathBuf>,
+
+    /// Prints how long each phase of assembly took to stderr.
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// The source file to assemble.
+    pub input: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ColorWhen {
+    /// Only if stderr is a terminal, and the environment doesn't say otherwise (e.g. `NO_COLOR`).
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorWhen {
+    pub fn for_stderr(self) -> ColorChoice {
+        match self {
+            Self::Auto if std::io::stderr().is_terminal() => ColorChoice::Auto,
+            Self::Auto | Self::Never => ColorChoice::Never,
+            Self::Always => ColorChoice::Always,
+        }
+    }
+}
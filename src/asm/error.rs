@@ -1,17 +1,27 @@
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Write as _},
+    io::Write,
+    str::FromStr,
+};
+
+use clap::ValueEnum;
 use codespan_reporting::{
-    diagnostic::{Diagnostic, Label},
+    diagnostic::{Diagnostic, Label, LabelStyle, Severity},
+    files::Files,
     term::{
-        termcolor::{ColorChoice, StandardStream},
+        termcolor::{ColorChoice, NoColor, StandardStream, WriteColor},
         Config,
     },
 };
+use parse_display::Display;
 
-use crate::{
-    fstack::Fstack,
-    language::{AsmError, AsmErrorKind, Location, ParseError, Warning, WarningId, WarningKind},
+use crate::asm::{
+    fstack::{DiagInfo, Frame, Fstack},
+    language::{AsmError, AsmErrorKind, Location, ParseError, Warning, WarningId},
 };
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum WarningState {
     Default,
     Disabled,
@@ -19,31 +29,514 @@ pub enum WarningState {
     Error,
 }
 
-#[derive(Debug)]
+/// A `-W` flag, which sets the state of one or several warnings.
+///
+/// `-W<name>` enables a warning or meta group, and `-Wno-<name>` disables it. Warnings with levels
+/// can also be set to a specific level with `-W<name>=<level>`, `0` disabling them; their bare name
+/// selects the default level.
+#[derive(Debug, Clone)]
+pub enum WarningFlag {
+    Set(Vec<(WarningId, WarningState)>),
+    /// `-Werror=<name>` enables warnings as errors, `-Wno-error=<name>` demotes them back.
+    Error(Vec<WarningId>, bool),
+    /// `-Werror` turns all enabled warnings into errors, `-Wno-error` undoes that.
+    AllErrors(bool),
+}
+
+#[derive(Debug, Display)]
+pub enum WarningFlagError {
+    #[display("Unknown warning flag \"{0}\"")]
+    Unknown(String),
+    #[display("Invalid level for warning flag \"{0}\"")]
+    BadLevel(String),
+}
+
+impl std::error::Error for WarningFlagError {}
+
+impl FromStr for WarningFlag {
+    type Err = WarningFlagError;
+
+    fn from_str(flag: &str) -> Result<Self, Self::Err> {
+        let (name, negated) = match flag.strip_prefix("no-") {
+            Some(name) => (name, true),
+            None => (flag, false),
+        };
+
+        if name == "error" {
+            return Ok(Self::AllErrors(!negated));
+        }
+        if let Some(name) = name.strip_prefix("error=") {
+            let ids = Self::parse_states(name, WarningState::Enabled)
+                .map_err(|_| WarningFlagError::Unknown(flag.to_string()))?
+                .into_iter()
+                .filter_map(|(id, state)| (state == WarningState::Enabled).then_some(id))
+                .collect();
+            return Ok(Self::Error(ids, !negated));
+        }
+
+        let state = if negated {
+            WarningState::Disabled
+        } else {
+            WarningState::Enabled
+        };
+        Self::parse_states(name, state)
+            .map(Self::Set)
+            .map_err(|err| match err {
+                WarningFlagError::Unknown(_) => WarningFlagError::Unknown(flag.to_string()),
+                WarningFlagError::BadLevel(_) => WarningFlagError::BadLevel(flag.to_string()),
+            })
+    }
+}
+
+impl WarningFlag {
+    /// Parses the warnings listed by a `; rgbasm-ignore: <name>, ...` comment, starting right
+    /// after its semicolon. Names that are not warnings are skipped.
+    pub fn parse_ignore_comment(comment: &str) -> Option<Vec<WarningId>> {
+        let line = comment.split(['\r', '\n']).next().unwrap_or_default();
+        let names = line.trim_start().strip_prefix("rgbasm-ignore:")?;
+        Some(
+            names
+                .split(',')
+                .filter_map(|name| Self::parse_states(name.trim(), WarningState::Disabled).ok())
+                .flatten()
+                .map(|(id, _)| id)
+                .collect(),
+        )
+    }
+
+    /// Resolves a warning, meta group, or warning level to the states it sets.
+    fn parse_states(
+        name: &str,
+        state: WarningState,
+    ) -> Result<Vec<(WarningId, WarningState)>, WarningFlagError> {
+        let set_all = |ids: &[WarningId]| ids.iter().map(|&id| (id, state)).collect();
+        let set_level = |levels: &[WarningId], level| {
+            levels
+                .iter()
+                .enumerate()
+                .map(|(i, &id)| {
+                    let enabled = i < usize::from(level);
+                    (
+                        id,
+                        if enabled {
+                            WarningState::Enabled
+                        } else {
+                            WarningState::Disabled
+                        },
+                    )
+                })
+                .collect()
+        };
+        let parametric = |name: &str| {
+            WarningId::PARAMETRIC
+                .iter()
+                .find(|(param_name, ..)| *param_name == name)
+        };
+
+        if let Some((base_name, level)) = name.split_once('=') {
+            let Some((_, levels, _)) = parametric(base_name) else {
+                return Err(WarningFlagError::Unknown(name.to_string()));
+            };
+            match level.parse() {
+                Ok(level)
+                    if state == WarningState::Enabled && usize::from(level) <= levels.len() =>
+                {
+                    Ok(set_level(levels, level))
+                }
+                _ => Err(WarningFlagError::BadLevel(name.to_string())),
+            }
+        } else if let Some((_, levels, default)) = parametric(name) {
+            Ok(match state {
+                WarningState::Enabled => set_level(levels, *default),
+                _ => set_all(levels),
+            })
+        } else if let Some((_, members)) = WarningId::META_GROUPS
+            .iter()
+            .find(|(group_name, _)| *group_name == name)
+        {
+            Ok(set_all(members))
+        } else {
+            WarningId::from_name(name)
+                .map(|id| set_all(&[id]))
+                .ok_or_else(|| WarningFlagError::Unknown(name.to_string()))
+        }
+    }
+}
+
+/// How diagnostics are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DiagnosticFormat {
+    /// Annotated source snippets, meant to be read by humans.
+    Human,
+    /// One JSON object per line, meant to be read by editors and CI.
+    Json,
+}
+
 pub struct Reporter {
-    writer: StandardStream,
+    writer: Box<dyn WriteColor>,
     config: Config,
+    format: DiagnosticFormat,
+
+    warning_levels: [WarningState; WarningId::NB_WARNINGS],
+    warnings_are_errors: bool,
+    nb_errors: usize,
+    /// Once this many errors have been reported, further diagnostics are only counted; 0 means
+    /// there is no limit.
+    max_errors: usize,
+    /// How many errors went unreported because the error limit had been reached.
+    nb_suppressed: usize,
+    /// Settings saved by `PUSHO`, most recent last.
+    saved: Vec<SavedSettings>,
+    /// If set, diagnostics are rendered into this instead of being written out.
+    collected: Option<Vec<String>>,
+    /// Warnings disabled on a single line by `; rgbasm-ignore:` comments,
+    /// as (file name, line, warning). Node IDs get reused, so the file is identified by name.
+    ignored: Vec<(String, usize, WarningId)>,
+    /// If set, diagnostics identical to an earlier one are only counted in here, instead of being
+    /// reported again. Maps each diagnostic to when it first occurred, and how many times it did.
+    seen: Option<HashMap<DedupKey, (usize, usize)>>,
+}
 
+/// What makes two diagnostics identical: their code, message, and primary location.
+type DedupKey = (Option<String>, String, String);
+
+/// The reporter's settings that `OPT` can change, and thus `PUSHO` saves.
+#[derive(Debug)]
+struct SavedSettings {
     warning_levels: [WarningState; WarningId::NB_WARNINGS],
     warnings_are_errors: bool,
+    max_errors: usize,
+}
+
+impl Debug for Reporter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Reporter")
+            .field("config", &self.config)
+            .field("format", &self.format)
+            .field("warning_levels", &self.warning_levels)
+            .field("warnings_are_errors", &self.warnings_are_errors)
+            .field("nb_errors", &self.nb_errors)
+            .field("max_errors", &self.max_errors)
+            .field("nb_suppressed", &self.nb_suppressed)
+            .field("saved", &self.saved)
+            .field("collected", &self.collected)
+            .field("ignored", &self.ignored)
+            .field("seen", &self.seen)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Assembles a [`Diagnostic`] piece by piece, so that all errors and warnings are laid out alike.
+#[derive(Debug)]
+pub struct DiagnosticBuilder(Diagnostic<usize>);
+
+impl DiagnosticBuilder {
+    pub fn new(severity: Severity, message: String) -> Self {
+        Self(Diagnostic::new(severity).with_message(message))
+    }
+
+    pub fn code(mut self, code: String) -> Self {
+        self.0.code = Some(code);
+        self
+    }
+
+    /// Points at what the diagnostic is about; nothing is pointed at if there is no location.
+    pub fn primary(mut self, span: &DiagInfo) -> Self {
+        if let Some((file_id, range)) = span {
+            self.0.labels.push(Label::primary(*file_id, range.clone()));
+        }
+        self
+    }
+
+    /// Points at something related, such as a previous definition.
+    pub fn secondary(mut self, span: &DiagInfo, message: &str) -> Self {
+        if let Some((file_id, range)) = span {
+            self.0
+                .labels
+                .push(Label::secondary(*file_id, range.clone()).with_message(message));
+        }
+        self
+    }
+
+    pub fn previous_definition(self, span: &DiagInfo) -> Self {
+        self.secondary(span, "Previously defined here")
+    }
+
+    pub fn notes(mut self, notes: Vec<String>) -> Self {
+        self.0.notes.extend(notes);
+        self
+    }
+
+    pub fn build(self) -> Diagnostic<usize> {
+        self.0
+    }
+}
+
+/// Renders a diagnostic, as `severity[code]: message` followed by the annotated source snippets.
+///
+/// This is the one place diagnostics get formatted, so that they all look alike.
+pub fn render(
+    writer: &mut dyn WriteColor,
+    config: &Config,
+    fstack: &Fstack,
+    diagnostic: &Diagnostic<usize>,
+) -> Result<(), codespan_reporting::files::Error> {
+    codespan_reporting::term::emit(writer, config, &fstack.get_files(), diagnostic)
+}
+
+/// Renders a diagnostic as a single line of JSON, with the primary label's position and the
+/// backtrace leading to it broken out into fields.
+pub fn render_json(
+    writer: &mut dyn Write,
+    fstack: &Fstack,
+    diagnostic: &Diagnostic<usize>,
+) -> Result<(), codespan_reporting::files::Error> {
+    let files = fstack.get_files();
+    let severity = match diagnostic.severity {
+        Severity::Bug => "bug",
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Note => "note",
+        Severity::Help => "help",
+    };
+
+    let mut json = format!("{{\"severity\":\"{severity}\",\"code\":");
+    match &diagnostic.code {
+        Some(code) => json_str(&mut json, code),
+        None => json.push_str("null"),
+    }
+    json.push_str(",\"message\":");
+    json_str(&mut json, &diagnostic.message);
+
+    match diagnostic
+        .labels
+        .iter()
+        .find(|label| label.style == LabelStyle::Primary)
+    {
+        Some(label) => {
+            let location = files.location(label.file_id, label.range.start)?;
+            json.push_str(",\"file\":");
+            json_str(&mut json, files.name(label.file_id)?);
+            write!(
+                json,
+                ",\"line\":{},\"col\":{},\"span\":{{\"start\":{},\"end\":{}}},\"backtrace\":[",
+                location.line_number, location.column_number, label.range.start, label.range.end,
+            )
+            .unwrap();
+            for (i, frame) in files.backtrace(label.file_id).iter().enumerate() {
+                if i != 0 {
+                    json.push(',');
+                }
+                match frame {
+                    Frame::File(name) => {
+                        json.push_str("{\"kind\":\"file\",\"name\":");
+                        json_str(&mut json, name);
+                        json.push('}');
+                    }
+                    Frame::Macro => json.push_str("{\"kind\":\"macro\"}"),
+                    Frame::Loop(iteration) => {
+                        write!(json, "{{\"kind\":\"loop\",\"iteration\":{iteration}}}").unwrap()
+                    }
+                }
+            }
+            json.push(']');
+        }
+        None => json
+            .push_str(",\"file\":null,\"line\":null,\"col\":null,\"span\":null,\"backtrace\":[]"),
+    }
+
+    json.push_str(",\"notes\":[");
+    for (i, note) in diagnostic.notes.iter().enumerate() {
+        if i != 0 {
+            json.push(',');
+        }
+        json_str(&mut json, note);
+    }
+    json.push_str("]}\n");
+
+    writer.write_all(json.as_bytes())?;
+    Ok(())
+}
+
+/// Appends a string to some JSON, as a quoted and escaped string literal.
+pub fn json_str(json: &mut String, string: &str) {
+    json.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+fn make_config() -> Config {
+    let mut config = Config::default();
+    // The defaults have poor contrast.
+    config.styles.primary_label_bug.set_intense(true);
+    config.styles.primary_label_error.set_intense(true);
+    config.styles.primary_label_warning.set_intense(true);
+    config.styles.primary_label_note.set_intense(true);
+    config.styles.primary_label_help.set_intense(true);
+    config.styles.secondary_label.set_intense(true);
+    config.styles.line_number.set_intense(true);
+    config.tab_width = 8;
+    config
+}
+
+impl AsmError<'_> {
+    /// Renders the error the same way a [`Reporter`] would, minus the colours.
+    pub fn render(self, fstack: &Fstack) -> String {
+        let (diagnostic, _) = Reporter::error_diagnostic(self.into());
+        let mut writer = NoColor::new(vec![]);
+        if let Err(err) = render(&mut writer, &make_config(), fstack, &diagnostic) {
+            return format!("Internal error when rendering diagnostic: {err}");
+        }
+        String::from_utf8(writer.into_inner()).expect("Diagnostics should be valid UTF-8")
+    }
 }
 
 impl Reporter {
     pub fn new(color_choice: ColorChoice) -> Self {
-        let mut config = Config::default();
-        // The defaults have poor contrast.
-        config.styles.primary_label_bug.set_intense(true);
-        config.styles.primary_label_error.set_intense(true);
-        config.styles.primary_label_warning.set_intense(true);
-        config.styles.primary_label_note.set_intense(true);
-        config.styles.primary_label_help.set_intense(true);
-        config.styles.secondary_label.set_intense(true);
-        config.styles.line_number.set_intense(true);
+        Self::with_writer(Box::new(StandardStream::stderr(color_choice)))
+    }
+
+    /// Creates a reporter that writes its diagnostics to an arbitrary sink, using ANSI colour codes
+    /// only if asked to. (Unlike with stderr, there is no terminal to auto-detect colour support of.)
+    #[cfg(test)]
+    pub fn with_color<W: Write + 'static>(color_choice: ColorChoice, writer: W) -> Self {
+        match color_choice {
+            ColorChoice::Always | ColorChoice::AlwaysAnsi => Self::with_writer(Box::new(
+                codespan_reporting::term::termcolor::Ansi::new(writer),
+            )),
+            ColorChoice::Auto | ColorChoice::Never => {
+                Self::with_writer(Box::new(NoColor::new(writer)))
+            }
+        }
+    }
+
+    /// Creates a reporter that writes its diagnostics to an arbitrary sink instead of stderr.
+    pub fn with_writer(writer: Box<dyn WriteColor>) -> Self {
         Self {
-            writer: StandardStream::stderr(color_choice),
-            config,
+            writer,
+            config: make_config(),
+            format: DiagnosticFormat::Human,
             warning_levels: [WarningState::Default; WarningId::NB_WARNINGS],
             warnings_are_errors: false,
+            nb_errors: 0,
+            max_errors: 20,
+            nb_suppressed: 0,
+            saved: Vec::new(),
+            collected: None,
+            ignored: Vec::new(),
+            seen: None,
+        }
+    }
+
+    /// Creates a reporter that keeps each diagnostic, rendered without colours, for
+    /// [`Self::take_collected`] to return.
+    pub fn collecting() -> Self {
+        Self {
+            collected: Some(Vec::new()),
+            ..Self::with_writer(Box::new(NoColor::new(std::io::sink())))
+        }
+    }
+
+    /// Returns the diagnostics collected so far, if this reporter was created by [`Self::collecting`].
+    pub fn take_collected(&mut self) -> Vec<String> {
+        self.collected
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    pub fn set_format(&mut self, format: DiagnosticFormat) {
+        self.format = format;
+    }
+
+    /// Sets how many columns tabs are expanded to in source snippets.
+    pub fn set_tab_width(&mut self, tab_width: usize) {
+        self.config.tab_width = tab_width;
+    }
+
+    /// How many errors have been reported so far, including warnings turned into errors, and
+    /// errors suppressed due to the error limit.
+    pub fn nb_errors(&self) -> usize {
+        self.nb_errors
+    }
+
+    /// How many errors went unreported because the error limit had been reached.
+    pub fn nb_suppressed(&self) -> usize {
+        self.nb_suppressed
+    }
+
+    /// Sets the error limit. This can be done mid-assembly (via `OPT X`), in which case errors
+    /// reported so far count towards the new limit.
+    pub fn set_max_errors(&mut self, max_errors: usize) {
+        self.max_errors = max_errors;
+    }
+
+    /// Saves the settings that `OPT` can change, for [`Self::pop_settings`] to restore; this is
+    /// done by `PUSHO`.
+    pub fn push_settings(&mut self) {
+        self.saved.push(SavedSettings {
+            warning_levels: self.warning_levels,
+            warnings_are_errors: self.warnings_are_errors,
+            max_errors: self.max_errors,
+        });
+    }
+
+    /// Restores the settings last saved by [`Self::push_settings`], if any; this is done by `POPO`.
+    pub fn pop_settings(&mut self) {
+        if let Some(saved) = self.saved.pop() {
+            self.warning_levels = saved.warning_levels;
+            self.warnings_are_errors = saved.warnings_are_errors;
+            self.max_errors = saved.max_errors;
+        }
+    }
+
+    /// Makes diagnostics identical to an earlier one be counted instead of reported again, for
+    /// [`Self::report_duplicates`] to summarize. Duplicate errors do not count towards the limit.
+    pub fn set_dedup(&mut self, dedup: bool) {
+        self.seen = dedup.then(HashMap::new);
+    }
+
+    /// Whether enough errors have been reported that no more diagnostics should be.
+    /// Any further diagnostics are likely to be fallout from the earlier errors, so they are only
+    /// counted, for [`Self::report_suppressed`] to mention.
+    pub fn reached_error_limit(&self) -> bool {
+        self.max_errors != 0 && self.nb_errors >= self.max_errors
+    }
+
+    pub fn set_warning_state(&mut self, id: WarningId, state: WarningState) {
+        self.warning_levels[id as usize] = state;
+    }
+
+    pub fn apply_warning_flag(&mut self, flag: &WarningFlag) {
+        match flag {
+            WarningFlag::Set(states) => {
+                for &(id, state) in states {
+                    self.set_warning_state(id, state);
+                }
+            }
+            WarningFlag::Error(ids, true) => {
+                for &id in ids {
+                    self.set_warning_state(id, WarningState::Error);
+                }
+            }
+            WarningFlag::Error(ids, false) => {
+                for &id in ids {
+                    if let WarningState::Error = self.warning_levels[id as usize] {
+                        self.set_warning_state(id, WarningState::Enabled);
+                    }
+                }
+            }
+            WarningFlag::AllErrors(are_errors) => self.warnings_are_errors = *are_errors,
         }
     }
 
@@ -69,47 +562,74 @@ impl Reporter {
         }
     }
 
-    fn make_primary_label(begin: &Location, end: Option<&Location>) -> Vec<Label<usize>> {
-        Fstack::make_diag_info(begin, end)
-            .map(|(file_id, range)| Label::primary(file_id, range))
-            .into_iter()
-            .collect()
-    }
+    fn report(&mut self, fstack: &Fstack, diagnostic: &Diagnostic<usize>) {
+        let mut buf = NoColor::new(vec![]);
+        let writer: &mut dyn WriteColor = if self.collected.is_some() {
+            &mut buf
+        } else {
+            self.writer.as_mut()
+        };
+        let res = match self.format {
+            DiagnosticFormat::Human => render(writer, &self.config, fstack, diagnostic),
+            DiagnosticFormat::Json => render_json(writer, fstack, diagnostic),
+        };
+        if let Err(err) = res {
+            eprintln!("Internal error when writing diagnostic: {err}");
+        }
 
-    fn make_warning_labels(
-        begin: &Location,
-        end: Option<&Location>,
-        kind: &WarningKind,
-    ) -> Vec<Label<usize>> {
-        let mut labels = Self::make_primary_label(begin, end);
-        kind.labels(&mut labels);
-        labels
+        if let Some(collected) = &mut self.collected {
+            collected.push(
+                String::from_utf8(buf.into_inner()).expect("Diagnostics should be valid UTF-8"),
+            );
+        }
     }
 
-    fn make_error_labels(
-        begin: &Location,
-        end: Option<&Location>,
-        kind: &AsmErrorKind,
-    ) -> Vec<Label<usize>> {
-        let mut labels = Self::make_primary_label(begin, end);
-        kind.labels(&mut labels);
-        labels
+    /// Builds the diagnostic for a warning, which has already been determined to be reported.
+    pub fn warning_diagnostic(warning: &Warning, is_error: bool) -> Diagnostic<usize> {
+        let id = WarningId::from(&warning.kind);
+
+        let builder = if is_error {
+            DiagnosticBuilder::new(Severity::Error, warning.kind.to_string())
+                .code(format!("-Werror={id}"))
+        } else {
+            DiagnosticBuilder::new(Severity::Warning, warning.kind.to_string())
+                .code(format!("-W{id}"))
+        };
+        warning
+            .kind
+            .labels(builder.primary(&Fstack::make_diag_info(&warning.begin, Some(&warning.end))))
+            .notes(warning.kind.notes())
+            .build()
     }
 
-    fn report(&mut self, fstack: &Fstack, diagnostic: &Diagnostic<usize>) {
-        if let Err(err) = codespan_reporting::term::emit(
-            &mut self.writer,
-            &self.config,
-            &fstack.get_files(),
-            diagnostic,
-        ) {
-            eprintln!("Internal error when writing diagnostic: {err}");
-        }
+    /// Builds the diagnostic for an error; the error's kind is handed back for further reporting.
+    pub fn error_diagnostic(error: ParseError) -> (Diagnostic<usize>, AsmErrorKind) {
+        let (begin, end, kind) = Self::extract_error_info(error);
+
+        let builder = DiagnosticBuilder::new(Severity::Error, kind.to_string()) // TODO: ew!
+            .primary(&Fstack::make_diag_info(&begin, end.as_ref()));
+        let diagnostic = kind.labels(builder).notes(kind.notes()).build();
+        (diagnostic, kind)
     }
 
     pub fn warn(&mut self, fstack: &Fstack, warning: Warning) {
         let id = WarningId::from(&warning.kind);
 
+        if !self.ignored.is_empty() {
+            if let Some((file_name, line)) = Self::line_of(fstack, &warning.begin) {
+                if self
+                    .ignored
+                    .iter()
+                    .any(|(ignored_file, ignored_line, ignored_id)| {
+                        (ignored_file, *ignored_line, *ignored_id as usize)
+                            == (&file_name, line, id as usize)
+                    })
+                {
+                    return;
+                }
+            }
+        }
+
         // Determine what to do based on configured warning levels.
         let is_error = match self.warning_levels[id as usize] {
             WarningState::Disabled => return,
@@ -121,44 +641,719 @@ impl Reporter {
             WarningState::Enabled | WarningState::Default => self.warnings_are_errors,
         };
 
-        let diagnostic = if is_error {
-            Diagnostic::error().with_code(format!("-Werror={id}"))
-        } else {
-            Diagnostic::warning().with_code(format!("-W{id}"))
-        }
-        .with_labels(Self::make_warning_labels(
-            &warning.begin,
-            Some(&warning.end),
-            &warning.kind,
-        ))
-        .with_message(warning.kind.to_string())
-        .with_notes(warning.kind.notes());
+        if self.reached_error_limit() {
+            if is_error {
+                self.nb_errors += 1;
+                self.nb_suppressed += 1;
+            }
+            return;
+        }
+        let diagnostic = Self::warning_diagnostic(&warning, is_error);
+        if self.is_duplicate(fstack, &diagnostic) {
+            return;
+        }
+        if is_error {
+            self.nb_errors += 1;
+        }
         self.report(fstack, &diagnostic);
 
         // TODO: print help
     }
 
-    pub fn report_error(&mut self, fstack: &Fstack, error: ParseError) {
-        let (begin, end, kind) = Self::extract_error_info(error);
+    /// Disables some warnings on the line after `location`'s only.
+    pub fn ignore_on_next_line(&mut self, fstack: &Fstack, location: &Location, ids: &[WarningId]) {
+        if let Some((file_name, line)) = Self::line_of(fstack, location) {
+            for &id in ids {
+                // The same comment may be read many times over, e.g. within a loop.
+                if !self
+                    .ignored
+                    .iter()
+                    .any(|(ignored_file, ignored_line, ignored_id)| {
+                        (ignored_file, *ignored_line, *ignored_id as usize)
+                            == (&file_name, line + 1, id as usize)
+                    })
+                {
+                    self.ignored.push((file_name.clone(), line + 1, id));
+                }
+            }
+        }
+    }
+
+    fn line_of(fstack: &Fstack, location: &Location) -> Option<(String, usize)> {
+        let (file_id, range) = Fstack::make_diag_info(location, None)?;
+        let files = fstack.get_files();
+        let line = files.line_index(file_id, range.start).ok()?;
+        Some((files.name(file_id).ok()?.to_string(), line))
+    }
 
-        let diagnostic = Diagnostic::error()
-            .with_labels(Self::make_error_labels(&begin, end.as_ref(), &kind))
-            .with_message(kind.to_string()) // TODO: ew!
-            .with_notes(kind.notes());
+    pub fn report_error(&mut self, fstack: &Fstack, error: ParseError) {
+        if self.reached_error_limit() {
+            self.nb_errors += 1;
+            self.nb_suppressed += 1;
+            return;
+        }
+        let (diagnostic, kind) = Self::error_diagnostic(error);
+        if self.is_duplicate(fstack, &diagnostic) {
+            return;
+        }
+        self.nb_errors += 1;
         self.report(fstack, &diagnostic);
 
         kind.report_help(|diag| self.report(fstack, diag));
     }
 
-    pub fn report_fatal_error(&mut self, fstack: &Fstack, error: ParseError) {
-        let (begin, end, kind) = Self::extract_error_info(error);
+    /// Reports an error that is not about any location, e.g. the input file being unreadable.
+    pub fn report_unlocated_error(&mut self, fstack: &Fstack, kind: AsmErrorKind) {
+        let diagnostic = DiagnosticBuilder::new(Severity::Error, kind.to_string())
+            .notes(kind.notes())
+            .build();
+        self.nb_errors += 1;
+        self.report(fstack, &diagnostic);
+    }
 
-        let mut notes = kind.notes();
-        notes.push("Aborted assembling due to this error being fatal".into());
-        let diagnostic = Diagnostic::error()
-            .with_labels(Self::make_error_labels(&begin, end.as_ref(), &kind))
-            .with_message(kind.to_string()) // TODO: ew!
-            .with_notes(notes);
+    pub fn report_fatal_error(&mut self, fstack: &Fstack, error: ParseError) {
+        if self.reached_error_limit() {
+            self.nb_errors += 1;
+            self.nb_suppressed += 1;
+            return;
+        }
+        let (mut diagnostic, _) = Self::error_diagnostic(error);
+        diagnostic
+            .notes
+            .push("Aborted assembling due to this error being fatal".into());
+        self.nb_errors += 1;
         self.report(fstack, &diagnostic);
     }
+
+    /// Records `diagnostic` if deduplicating, and returns whether an identical one was already.
+    fn is_duplicate(&mut self, fstack: &Fstack, diagnostic: &Diagnostic<usize>) -> bool {
+        let Some(seen) = &mut self.seen else {
+            return false;
+        };
+        // Node IDs get reused, so the location must be resolved now.
+        let location = diagnostic
+            .labels
+            .iter()
+            .find(|label| label.style == LabelStyle::Primary)
+            .map(|label| {
+                let diag_info = Some((label.file_id, label.range.clone()));
+                fstack.display_diag_info(&diag_info).to_string()
+            })
+            .unwrap_or_default();
+        let key = (
+            diagnostic.code.clone(),
+            diagnostic.message.clone(),
+            location,
+        );
+
+        let nb_seen = seen.len();
+        let (_, count) = seen.entry(key).or_insert((nb_seen, 0));
+        *count += 1;
+        *count > 1
+    }
+
+    /// Reports how many times each diagnostic that was deduplicated occurred.
+    pub fn report_duplicates(&mut self, fstack: &Fstack) {
+        let Some(seen) = self.seen.take() else {
+            return;
+        };
+        let mut duplicates: Vec<_> = seen
+            .into_iter()
+            .filter(|(_, (_, count))| *count > 1)
+            .collect();
+        duplicates.sort_unstable_by_key(|(_, (order, _))| *order);
+
+        for ((code, message, location), (_, count)) in duplicates {
+            let mut diagnostic = Diagnostic::note()
+                .with_message(format!("{location}: {message} (occurred {count} times)"));
+            diagnostic.code = code;
+            self.report(fstack, &diagnostic);
+        }
+    }
+
+    /// Reports how many errors went unreported due to the error limit, if any did.
+    pub fn report_suppressed(&mut self, fstack: &Fstack) {
+        let nb_suppressed = self.nb_suppressed();
+        if nb_suppressed != 0 {
+            let diagnostic = Diagnostic::note()
+                .with_message(format!(
+                    "{nb_suppressed} more error{} suppressed",
+                    if nb_suppressed == 1 { "" } else { "s" }
+                ))
+                .with_notes(vec!["Use `-X` to change the limit".into()]);
+            self.report(fstack, &diagnostic);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::asm::{
+        assemble::{assemble_str, SharedBuf},
+        language::{Lexer, Location, Tokenizer},
+        options::Options,
+        symbols::Symbols,
+        test_utils::{assemble, assemble_with, error_lines},
+        Storage,
+    };
+
+    #[test]
+    fn error_caret() {
+        let root_file =
+            Storage::from_readable("caret.asm".to_string().into(), &b"    db 1, 2\n"[..])
+                .expect("Reading from a slice cannot fail");
+        let fstack = Fstack::new(Rc::new(root_file), vec![]);
+        let reporter = RefCell::new(Reporter::new(ColorChoice::Never));
+        let symbols = RefCell::new(Symbols::new());
+        let lexer = RefCell::new(Lexer::new());
+        let macro_args = RefCell::new(Vec::new());
+        let (begin, _, end) = Tokenizer::new(&fstack, &lexer, &macro_args, &reporter, &symbols)
+            .next()
+            .expect("There should be a token")
+            .expect("The token should be valid");
+
+        assert_eq!(
+            AsmError::new(begin, end, AsmErrorKind::DataOutsideSection).render(&fstack),
+            r#"error: Data found outside of any section
+  ┌─ caret.asm:1:5
+  │
+1 │     db 1, 2
+  │     ^^
+
+"#
+        );
+    }
+
+    #[test]
+    fn merged_span() {
+        let root_file =
+            Storage::from_readable("merge.asm".to_string().into(), &b"    ld [hl], 1, 2\n"[..])
+                .expect("Reading from a slice cannot fail");
+        let fstack = Fstack::new(Rc::new(root_file), vec![]);
+        let symbols = RefCell::new(Symbols::new());
+        let lexer = RefCell::new(Lexer::new());
+        let macro_args = RefCell::new(Vec::new());
+        let reporter = RefCell::new(Reporter::new(ColorChoice::Never));
+        let spans: Vec<_> = Tokenizer::new(&fstack, &lexer, &macro_args, &reporter, &symbols)
+            .map(|token| token.expect("The token should be valid"))
+            .map(|(begin, _, end)| (begin, end))
+            .collect();
+
+        // Tokens 1 to 7 are `[`, `hl`, `]`, `,`, `1`, `,`, and `2`.
+        let (begin, end) = Location::merge(spans[1].clone(), spans[2].clone());
+        assert_eq!((begin.offset(), end.offset()), (7, 10));
+        let (begin, end) = Location::merge(spans[7].clone(), spans[1].clone());
+        assert_eq!((begin.offset(), end.offset()), (7, 17));
+
+        // A syntax error underlines all of the excess operands.
+        assert_eq!(
+            assemble("merge.asm", "SECTION \"a\", ROM0\n    ld [hl], 1, 2\n"),
+            r#"error: Syntax error: unexpected ","
+  ┌─ merge.asm:2:15
+  │
+2 │     ld [hl], 1, 2
+  │               ^^^
+  │
+  = Expected ")", or a newline
+
+"#
+        );
+    }
+
+    #[test]
+    fn ignore_comment() {
+        let diagnostics = assemble(
+            "ignore.asm",
+            concat!(
+                "MACRO m\n",
+                "    db \\1 ; rgbasm-ignore: truncation, unknown-warning\n",
+                "    db \\1\n",
+                "ENDM\n",
+                "SECTION \"a\", ROM0\n",
+                "; rgbasm-ignore: truncation\n",
+                "db 256\n",
+                "db 257\n",
+                "m 258\n",
+            ),
+        );
+        // Only the lines after the comments are affected, even when a macro arg is expanded there.
+        let warnings: Vec<_> = diagnostics
+            .lines()
+            .filter(|line| line.starts_with(|c: char| c.is_ascii_alphanumeric()))
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                "warning[-Wtruncation=1]: Value $101 does not fit in 8 bits (from -128 to 255, 2 too large)",
+                "8 │ db 257",
+                "warning[-Wtruncation=1]: Value $102 does not fit in 8 bits (from -128 to 255, 3 too large)",
+                "2 │     db \\1 ; rgbasm-ignore: truncation, unknown-warning",
+            ],
+            "{diagnostics}"
+        );
+    }
+
+    #[test]
+    fn ignore_comment_in_include() {
+        let resolver = |path: &str| match path {
+            "a.inc" => Some("; rgbasm-ignore: truncation\nnop\n".to_string()),
+            "b.inc" => Some("nop\ndb 256\n".to_string()),
+            _ => None,
+        };
+        let result = assemble_str(
+            "SECTION \"a\", ROM0\nINCLUDE \"a.inc\"\nnop\nnop\nINCLUDE \"b.inc\"\n",
+            &Options::default(),
+            resolver,
+            |_, _| (),
+        );
+        // `b.inc` may reuse `a.inc`'s node, but not its ignored lines.
+        assert_eq!(result.diagnostics.len(), 1, "{:?}", result.diagnostics);
+        assert!(
+            result.diagnostics[0].contains("b.inc:2:"),
+            "{:?}",
+            result.diagnostics
+        );
+    }
+
+    #[test]
+    fn section_redefinition() {
+        assert_eq!(
+            assemble("redef.asm", "SECTION \"a\", ROM0\nSECTION \"a\", ROM0\n"),
+            r#"error: a is already defined
+  ┌─ redef.asm:2:1
+  │
+1 │ SECTION "a", ROM0
+  │ ----------------- Previously defined here
+2 │ SECTION "a", ROM0
+  │ ^^^^^^^^^^^^^^^^^
+
+"#
+        );
+    }
+
+    #[test]
+    fn diagnostic_builder() {
+        let storage = Storage::from_readable("two.asm".to_string().into(), &b"Foo:\nFoo:\n"[..])
+            .expect("Reading from a slice cannot fail");
+        let fstack = Fstack::new(Rc::new(storage), vec![]);
+        let diagnostic = DiagnosticBuilder::new(Severity::Error, "Foo is already defined".into())
+            .primary(&Some((0, 5..8)))
+            .previous_definition(&Some((0, 0..3)))
+            .notes(vec!["Labels may only be defined once".into()])
+            .build();
+
+        let mut writer = NoColor::new(vec![]);
+        render(&mut writer, &make_config(), &fstack, &diagnostic).unwrap();
+        assert_eq!(
+            String::from_utf8(writer.into_inner()).unwrap(),
+            r#"error: Foo is already defined
+  ┌─ two.asm:2:1
+  │
+1 │ Foo:
+  │ --- Previously defined here
+2 │ Foo:
+  │ ^^^
+  │
+  = Labels may only be defined once
+
+"#
+        );
+    }
+
+    #[test]
+    fn unlocated_error() {
+        // An unreadable input file leaves nothing to point at but an empty stand-in.
+        let storage = Storage::from_readable("bad.asm".to_string().into(), &b""[..])
+            .expect("Reading from a slice cannot fail");
+        let fstack = Fstack::new(Rc::new(storage), vec![]);
+        let buf = SharedBuf::default();
+        let mut reporter = Reporter::with_writer(Box::new(NoColor::new(buf.clone())));
+        let err = std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "invalid UTF-8 at byte offset 25",
+        );
+        reporter.report_unlocated_error(
+            &fstack,
+            AsmErrorKind::InputReadError("bad.asm".to_string().into(), err),
+        );
+        assert_eq!(reporter.nb_errors(), 1);
+        assert_eq!(
+            buf.contents(),
+            "error: Unable to read input file \"bad.asm\": invalid UTF-8 at byte offset 25\n\n"
+        );
+    }
+
+    #[test]
+    fn colors() {
+        let source = "SECTION \"a\", ROM0\nSECTION \"a\", ROM0\n";
+        let assemble_colored = |color_choice| {
+            let buf = SharedBuf::default();
+            let writer = buf.clone();
+            assemble_with(
+                "colors.asm",
+                source,
+                |reporter, _| *reporter = Reporter::with_color(color_choice, writer),
+                |_| (),
+            );
+            buf.contents()
+        };
+
+        assert!(assemble_colored(ColorChoice::Always).contains("\x1b["));
+        assert!(!assemble_colored(ColorChoice::Never).contains('\x1b'));
+    }
+
+    #[test]
+    fn json_diagnostics() {
+        let (diagnostics, ()) = assemble_with(
+            "json.asm",
+            "SECTION \"a\", ROM0\nSECTION \"a\", ROM0\n",
+            |reporter, _| reporter.set_format(DiagnosticFormat::Json),
+            |_| (),
+        );
+        assert_eq!(
+            diagnostics,
+            concat!(
+                r#"{"severity":"error","code":null,"message":"a is already defined","file":"json.asm","#,
+                r#""line":2,"col":1,"span":{"start":18,"end":35},"backtrace":[{"kind":"file","name":"json.asm"}],"notes":[]}"#,
+                "\n",
+            )
+        );
+    }
+
+    #[test]
+    fn tab_width() {
+        let (diagnostics, ()) = assemble_with(
+            "tabs.asm",
+            "SECTION \"a\", ROM0\n\tld a,\t$1FF\t; comment\n",
+            |reporter, _| reporter.set_tab_width(4),
+            |_| (),
+        );
+        assert_eq!(
+            diagnostics,
+            r#"warning[-Wtruncation=1]: Value $1FF does not fit in 8 bits (from -128 to 255, 256 too large)
+  ┌─ tabs.asm:2:8
+  │
+2 │     ld a,   $1FF    ; comment
+  │             ^^^^
+
+"#
+        );
+    }
+
+    #[test]
+    fn warning_flags() {
+        let source = "SECTION \"a\", ROM0\nadd b\nld a, $1FF\nOPT q\n";
+        let with_flags = |flags: &'static [&'static str]| {
+            move |reporter: &mut Reporter, _: &mut _| {
+                for flag in flags {
+                    reporter.apply_warning_flag(&flag.parse().unwrap());
+                }
+            }
+        };
+
+        let (diagnostics, ()) = assemble_with(
+            "flags.asm",
+            source,
+            with_flags(&["no-everything", "truncation"]),
+            |_| (),
+        );
+        assert_eq!(
+            diagnostics,
+            r#"warning[-Wtruncation=1]: Value $1FF does not fit in 8 bits (from -128 to 255, 256 too large)
+  ┌─ flags.asm:3:7
+  │
+3 │ ld a, $1FF
+  │       ^^^^
+
+"#
+        );
+
+        let (diagnostics, ()) = assemble_with(
+            "flags.asm",
+            source,
+            with_flags(&["implicit-a", "no-truncation", "no-invalid-opt"]),
+            |_| (),
+        );
+        assert_eq!(
+            diagnostics,
+            r#"warning[-Wimplicit-a]: Destination operand `a` is implicit
+  ┌─ flags.asm:2:5
+  │
+2 │ add b
+  │     ^
+
+"#
+        );
+
+        assert!(matches!(
+            "truncation=3".parse::<WarningFlag>(),
+            Err(WarningFlagError::BadLevel(_))
+        ));
+        assert!(matches!(
+            "no-such-warning".parse::<WarningFlag>(),
+            Err(WarningFlagError::Unknown(_))
+        ));
+    }
+
+    #[test]
+    fn werror() {
+        let source = "SECTION \"a\", ROM0\nld a, $1FF\nOPT q\n";
+        let with_flags = |flags: &'static [&'static str]| {
+            move |reporter: &mut Reporter, _: &mut _| {
+                for flag in flags {
+                    reporter.apply_warning_flag(&flag.parse().unwrap());
+                }
+            }
+        };
+
+        let (diagnostics, ()) = assemble_with(
+            "werror.asm",
+            source,
+            with_flags(&["error=truncation"]),
+            |_| (),
+        );
+        assert_eq!(
+            diagnostics,
+            r#"error[-Werror=truncation=1]: Value $1FF does not fit in 8 bits (from -128 to 255, 256 too large)
+  ┌─ werror.asm:2:7
+  │
+2 │ ld a, $1FF
+  │       ^^^^
+
+warning[-Winvalid-opt]: Unknown option 'q'
+  ┌─ werror.asm:3:5
+  │
+3 │ OPT q
+  │     ^
+
+"#
+        );
+
+        let (diagnostics, ()) = assemble_with(
+            "werror.asm",
+            source,
+            with_flags(&["error=truncation", "no-error=truncation", "error"]),
+            |_| (),
+        );
+        assert!(diagnostics.starts_with("error[-Werror=truncation=1]"));
+        assert!(diagnostics.contains("error[-Werror=invalid-opt]"));
+
+        let (diagnostics, ()) = assemble_with(
+            "werror.asm",
+            source,
+            with_flags(&["error=truncation", "no-error=truncation"]),
+            |_| (),
+        );
+        assert!(diagnostics.starts_with("warning[-Wtruncation=1]"));
+    }
+
+    #[test]
+    fn error_recovery() {
+        let source = "SECTION \"a\", ROM0\nld a, [bc+de]\nds -1\nSECTION \"a\", ROM0\nnop\n";
+        let nb_errors = |diagnostics: &str| error_lines(diagnostics).len();
+
+        let (diagnostics, ()) = assemble_with("recovery.asm", source, |_, _| {}, |_| ());
+        assert_eq!(nb_errors(&diagnostics), 3, "{diagnostics}");
+
+        let (diagnostics, ()) = assemble_with(
+            "recovery.asm",
+            source,
+            |reporter, _| reporter.set_max_errors(2),
+            |_| (),
+        );
+        assert_eq!(nb_errors(&diagnostics), 2, "{diagnostics}");
+    }
+
+    #[test]
+    fn max_errors() {
+        let source = concat!(
+            "MACRO bad\n",
+            "    ds -1\n",
+            "    ds -1\n",
+            "ENDM\n",
+            "SECTION \"a\", ROM0\n",
+            "ds -1\n",
+            "bad\n",
+            "bad\n",
+            "bad\n",
+            "ds -1\n",
+        );
+        let (diagnostics, ()) = assemble_with(
+            "max_errors.asm",
+            source,
+            |reporter, _| reporter.set_max_errors(5),
+            |_| (),
+        );
+        let headers: Vec<_> = diagnostics
+            .lines()
+            .filter(|line| line.starts_with(|c: char| c.is_ascii_alphabetic()))
+            .collect();
+        assert_eq!(headers.len(), 6, "{diagnostics}");
+        assert!(headers[..5].iter().all(|line| line.starts_with("error")));
+        assert_eq!(
+            headers[5], "note: 3 more errors suppressed",
+            "{diagnostics}"
+        );
+    }
+
+    #[test]
+    fn opt_max_errors() {
+        let source = concat!(
+            "SECTION \"a\", ROM0\n",
+            "PUSHO\n",
+            "OPT X5, Wimplicit-a\n",
+            "ds -1\n",
+            "ds -1\n",
+            "ds -1\n",
+            "add 5\n",
+            "POPO\n",
+            "add 5\n",
+            "ds -1\n",
+        );
+        let (diagnostics, ()) = assemble_with(
+            "opt_x.asm",
+            source,
+            |reporter, _| reporter.set_max_errors(2),
+            |_| (),
+        );
+        let headers: Vec<_> = diagnostics
+            .lines()
+            .filter(|line| line.starts_with(|c: char| c.is_ascii_alphabetic()))
+            .collect();
+        assert_eq!(
+            headers,
+            [
+                "error: Cannot reserve a negative amount of bytes (-1)",
+                "error: Cannot reserve a negative amount of bytes (-1)",
+                "error: Cannot reserve a negative amount of bytes (-1)",
+                "warning[-Wimplicit-a]: Destination operand `a` is implicit",
+                "note: 1 more error suppressed",
+            ],
+            "{diagnostics}"
+        );
+    }
+
+    #[test]
+    fn dedup() {
+        let source = "MACRO m\n    add 5\nENDM\nSECTION \"a\", ROM0\nREPT 100\n    m\nENDR\n";
+        let headers = |dedup| {
+            let (diagnostics, ()) = assemble_with(
+                "dedup.asm",
+                source,
+                |reporter, _| {
+                    reporter.set_warning_state(WarningId::ImplicitA, WarningState::Enabled);
+                    reporter.set_dedup(dedup);
+                },
+                |_| (),
+            );
+            diagnostics
+                .lines()
+                .filter(|line| line.starts_with(|c: char| c.is_ascii_alphabetic()))
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(headers(false).len(), 100);
+        assert_eq!(
+            headers(true),
+            [
+                "warning[-Wimplicit-a]: Destination operand `a` is implicit",
+                "note[-Wimplicit-a]: dedup.asm:2:9: Destination operand `a` is implicit (occurred 100 times)",
+            ]
+        );
+    }
+
+    /// Enables the `-Wpedantic` group, like `--pedantic` does.
+    fn pedantic(reporter: &mut Reporter, _: &mut Options) {
+        for &id in WarningId::PEDANTIC {
+            reporter.set_warning_state(id, WarningState::Enabled);
+        }
+    }
+
+    #[test]
+    fn pedantic_implicit_a() {
+        let source = "SECTION \"a\", ROM0\nadd b\nadd a, b\n";
+
+        assert_eq!(assemble("implicit_a.asm", source), "");
+        assert_eq!(
+            assemble_with("implicit_a.asm", source, pedantic, |_| ()).0,
+            r#"warning[-Wimplicit-a]: Destination operand `a` is implicit
+  ┌─ implicit_a.asm:2:5
+  │
+2 │ add b
+  │     ^
+
+"#
+        );
+    }
+
+    #[test]
+    fn pedantic_large_ds() {
+        // Only reserving ROM is worth warning about, as RAM is never part of the ROM image.
+        let source = "SECTION \"a\", ROM0\nds 256\nSECTION \"b\", WRAM0\nds 256\n";
+
+        assert_eq!(assemble("large_ds.asm", source), "");
+        assert_eq!(
+            assemble_with("large_ds.asm", source, pedantic, |_| ()).0,
+            r#"warning[-Wlarge-ds]: Reserving 256 bytes of ROM with `ds`
+  ┌─ large_ds.asm:2:1
+  │
+2 │ ds 256
+  │ ^^^^^^
+
+"#
+        );
+    }
+
+    #[test]
+    fn pedantic_unlabeled_data() {
+        let source = "SECTION \"a\", ROM0\nLabel:\ndb 1\nSECTION \"b\", ROM0\ndb 2\n";
+
+        assert_eq!(assemble("unlabeled.asm", source), "");
+        assert_eq!(
+            assemble_with("unlabeled.asm", source, pedantic, |_| ()).0,
+            r#"warning[-Wunlabeled-data]: Data is not preceded by any label
+  ┌─ unlabeled.asm:5:1
+  │
+5 │ db 2
+  │ ^^^^
+
+"#
+        );
+    }
+
+    #[test]
+    fn implicit_a_opt() {
+        let source = concat!(
+            "SECTION \"a\", ROM0\n",
+            "add 5\n",
+            "OPT Wimplicit-a\n",
+            "add 5\n",
+            "add a, 5\n",
+            "OPT Werror=implicit-a\n",
+            "add 5\n",
+            "OPT Wno-implicit-a, Wbogus\n",
+            "add 5\n",
+        );
+        let diagnostics = assemble("implicit_a.asm", source);
+        let headers: Vec<_> = diagnostics
+            .lines()
+            .filter(|line| line.starts_with(|c: char| c.is_ascii_alphanumeric()))
+            .collect();
+        assert_eq!(
+            headers,
+            [
+                "warning[-Wimplicit-a]: Destination operand `a` is implicit",
+                "4 │ add 5",
+                "error[-Werror=implicit-a]: Destination operand `a` is implicit",
+                "7 │ add 5",
+                "warning[-Winvalid-opt]: Invalid value for option 'W' in \"Wbogus\"",
+                "8 │ OPT Wno-implicit-a, Wbogus",
+            ],
+            "{diagnostics}"
+        );
+    }
 }
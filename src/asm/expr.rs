@@ -1,17 +1,18 @@
-use rgbds::{
+use crate::{
     rpn::{Command, EvalError, Rpn},
     RelocKind,
 };
 
-use crate::{
-    language::{AsmError, Location, ParseError, SymEvalErrKind},
+use crate::asm::{
+    input::Encoding,
+    language::{AsmError, Location, ParseError, StringValue, SymEvalErrKind},
     macro_args::MacroArgs,
     sections::Sections,
     symbols::Symbols,
 };
 
 /// Importantly, the two locations `begin` and `end` do not necessarily represent the full expression, but e.g. the location of the sub-expression that generated the current error.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Expression<'fstack> {
     pub begin: Location<'fstack>,
     pub end: Location<'fstack>,
@@ -42,6 +43,65 @@ impl<'fstack> Expression<'fstack> {
         }
     }
 
+    /// `SIZEOF` a section, which is only computed now if the section cannot change size anymore.
+    pub fn sizeof_sect(
+        begin: Location<'fstack>,
+        end: Location<'fstack>,
+        name: &str,
+        sections: &Sections,
+    ) -> Self {
+        Self {
+            begin,
+            end,
+            rpn: Ok(match sections.size_of(name) {
+                Some(size) => Rpn::constant(size as u32),
+                None => Rpn::sizeof_sect(name),
+            }),
+        }
+    }
+
+    /// `STARTOF` a section, which is only computed now if the section has a fixed address.
+    pub fn startof_sect(
+        begin: Location<'fstack>,
+        end: Location<'fstack>,
+        name: &str,
+        sections: &Sections,
+    ) -> Self {
+        Self {
+            begin,
+            end,
+            rpn: Ok(match sections.start_of(name) {
+                Some(addr) => Rpn::constant(addr.into()),
+                None => Rpn::startof_sect(name),
+            }),
+        }
+    }
+
+    /// `SECTSTART`, i.e. the start address of the section that labels currently go into.
+    /// If that section is floating, the address is left for the linker to compute if `defer` is
+    /// set, and is an error otherwise.
+    pub fn sect_start(
+        begin: Location<'fstack>,
+        end: Location<'fstack>,
+        defer: bool,
+        sections: &Sections,
+    ) -> Self {
+        let rpn = match sections.active_section() {
+            None => Err(SymEvalErrKind::SectStartOutsideSection.into()),
+            Some(section) => {
+                let id = section.pc_section();
+                match sections.try_get_addr(id) {
+                    Some(addr) => Ok(Rpn::constant(addr.into())),
+                    None if defer => Ok(Rpn::startof_sect(sections.name_of(id))),
+                    None => Err(
+                        SymEvalErrKind::FloatingSection(sections.name_of(id).to_string()).into(),
+                    ),
+                }
+            }
+        };
+        Self { begin, end, rpn }
+    }
+
     // These are separate from `binary_op` because we don't have a full expression,
     // we'd like to avoid constructing bogus locations when we can just spawn a tiny RPN expression.
     // Plus it avoids potentially mucking with any internal assumptions in `binary_op`.
@@ -113,6 +173,26 @@ impl<'fstack> Expression<'fstack> {
         }
     }
 
+    /// Enumerates the IDs of the symbols that the expression depends on.
+    pub fn references(&self) -> impl Iterator<Item = u32> + '_ {
+        self.rpn.iter().flat_map(Rpn::references)
+    }
+
+    /// Replaces the references to symbols whose value is already known with that value, so that
+    /// evaluating the expression later yields the same result as now, e.g. even if `@` moved.
+    pub fn fold_known_symbols(
+        self,
+        symbols: &Symbols,
+        macro_args: Option<&MacroArgs>,
+        sections: &Sections,
+    ) -> Self {
+        let get_sym_value = |id| symbols.get_number_from_id(id, macro_args, sections).ok();
+        Self {
+            rpn: self.rpn.map(|rpn| rpn.fold_symbols(get_sym_value)),
+            ..self
+        }
+    }
+
     pub fn try_eval(
         self,
         symbols: &Symbols,
@@ -143,6 +223,43 @@ pub enum ByteOrExpr<'fstack> {
     Expr(Location<'fstack>, Location<'fstack>, Rpn, RelocKind),
 }
 
+/// One of the arguments to `db`, `dw`, or `dl`.
+#[derive(Debug)]
+pub enum DataItem<'fstack> {
+    String(Location<'fstack>, StringValue, Location<'fstack>),
+    Expr(Expression<'fstack>),
+}
+
+impl<'fstack> DataItem<'fstack> {
+    /// Lowers the argument into units of the given width: each character of a string yields one.
+    pub fn lower(
+        self,
+        kind: RelocKind,
+        encoding: Encoding,
+        data: &mut Vec<ByteOrExpr<'fstack>>,
+    ) -> Result<(), ParseError<'fstack>> {
+        match self {
+            // TODO: apply the charmap (and warn about unmapped chars) once charmaps exist
+            Self::String(begin, string, end) => {
+                data.extend(string.bytes(encoding).into_iter().map(|byte| match kind {
+                    RelocKind::Byte => ByteOrExpr::Byte(byte),
+                    _ => ByteOrExpr::Expr(
+                        begin.clone(),
+                        end.clone(),
+                        Rpn::constant(byte.into()),
+                        kind,
+                    ),
+                }));
+            }
+            Self::Expr(expr) => {
+                let (begin, end, rpn) = expr.into_raw_parts()?;
+                data.push(ByteOrExpr::Expr(begin, end, rpn, kind));
+            }
+        }
+        Ok(())
+    }
+}
+
 impl<'fstack> ByteOrExpr<'fstack> {
     pub fn try_from_expr(
         expr: Expression<'fstack>,
@@ -1,15 +1,21 @@
 use std::{
     cell::{Cell, RefCell},
+    collections::HashSet,
+    fmt::{Debug, Display},
+    fs::File,
+    io,
     num::NonZeroUsize,
     ops::{Deref, Range},
+    path::{Path, PathBuf},
     rc::Rc,
 };
 
 use codespan_reporting::files::Files;
 
-use crate::{
-    input::Storage,
+use crate::asm::{
+    input::{Binary, Encoding, Storage},
     language::{Lexer, Location},
+    macro_args::MacroArgs,
     SourceString,
 };
 
@@ -21,6 +27,31 @@ pub struct Fstack(RefCell<FstackImpl>);
 struct FstackImpl {
     nodes: Vec<Node>,
     cur_node_id: Option<NonZeroUsize>,
+    includes: Includes,
+    /// How `INCLUDE`d files are decoded.
+    encoding: Encoding,
+    /// Files that used `ONCE`, which further `INCLUDE`s skip; see [`Fstack::file_key`].
+    once_files: HashSet<PathBuf>,
+}
+
+/// Where `INCLUDE`d files are read from.
+enum Includes {
+    /// The filesystem; these directories are searched after the including file's own directory.
+    Paths(Vec<PathBuf>),
+    /// For assembling without touching the filesystem.
+    Resolver(Box<IncludeResolver>),
+}
+
+/// Maps an `INCLUDE`d path to the file's contents, or `None` if there is no such file.
+pub type IncludeResolver = dyn Fn(&str) -> Option<String>;
+
+impl Debug for Includes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Paths(paths) => f.debug_tuple("Paths").field(paths).finish(),
+            Self::Resolver(_) => f.debug_tuple("Resolver").finish_non_exhaustive(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -52,7 +83,23 @@ pub struct Node {
 enum NodeKind {
     File(Rc<Storage>),
     Macro((), Rc<SourceString>),
-    Loop(u32),
+    Loop(Rc<SourceString>, LoopState),
+}
+
+#[derive(Debug)]
+struct LoopState {
+    /// Which iteration is running, starting from 1.
+    iteration: u32,
+    nb_iterations: u32,
+    for_var: Option<ForVar>,
+}
+
+/// The variable that a `FOR` loop steps through.
+#[derive(Debug)]
+pub struct ForVar {
+    pub name: SourceString,
+    pub value: i32,
+    pub step: i32,
 }
 
 fn idx(node_id: NonZeroUsize) -> usize {
@@ -62,16 +109,62 @@ fn idx(node_id: NonZeroUsize) -> usize {
 /// Convenience shorthand.
 pub type DiagInfo = Option<(usize, Range<usize>)>;
 
+/// Formats a [`DiagInfo`] as `file:line:col`, pointing at the start of its range.
+#[derive(Debug)]
+pub struct DisplayDiagInfo<'a>(&'a Fstack, &'a DiagInfo);
+
+impl Display for DisplayDiagInfo<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Some((file_id, range)) = self.1 else {
+            return f.write_str("<builtin>");
+        };
+        let files = self.0.get_files();
+        match (files.name(*file_id), files.location(*file_id, range.start)) {
+            (Ok(name), Ok(location)) => write!(
+                f,
+                "{name}:{}:{}",
+                location.line_number, location.column_number
+            ),
+            _ => f.write_str("<invalid location>"),
+        }
+    }
+}
+
 impl Fstack {
-    pub fn new(root_file: Rc<Storage>) -> Self {
+    pub fn new(root_file: Rc<Storage>, include_paths: Vec<PathBuf>) -> Self {
+        Self::with_includes(root_file, Includes::Paths(include_paths))
+    }
+
+    /// Creates a file stack whose `INCLUDE`s are looked up using `resolver` instead of the filesystem.
+    pub fn with_resolver<R: Fn(&str) -> Option<String> + 'static>(
+        root_file: Rc<Storage>,
+        resolver: R,
+    ) -> Self {
+        Self::with_includes(root_file, Includes::Resolver(Box::new(resolver)))
+    }
+
+    fn with_includes(root_file: Rc<Storage>, includes: Includes) -> Self {
         let this = Self(RefCell::new(FstackImpl {
             nodes: vec![],
             cur_node_id: None,
+            includes,
+            encoding: Encoding::default(),
+            once_files: HashSet::new(),
         }));
         this.push_new_node(NodeKind::File(root_file));
         this
     }
 
+    /// Sets how files `INCLUDE`d from now on are decoded.
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.0.get_mut().encoding = encoding;
+    }
+
+    /// How source files are decoded, which strings' text must be encoded back with.
+    pub fn encoding(&self) -> Encoding {
+        self.0.borrow().encoding
+    }
+
     pub fn make_diag_info(begin: &Location<'_>, end: Option<&Location<'_>>) -> DiagInfo {
         // A lack of handle means a "default" location, which should be mapped to the root node,
         // which is necessarily a file, and that always has some storage.
@@ -96,6 +189,10 @@ impl Fstack {
         })
     }
 
+    pub fn display_diag_info<'a>(&'a self, diag_info: &'a DiagInfo) -> DisplayDiagInfo<'a> {
+        DisplayDiagInfo(self, diag_info)
+    }
+
     pub fn get_files(&self) -> Binder<'_> {
         Binder(self.0.borrow())
     }
@@ -159,6 +256,110 @@ impl Fstack {
         }
     }
 
+    /// How many nodes are currently nested, including the root file.
+    pub fn depth(&self) -> usize {
+        let inner = self.0.borrow();
+        std::iter::successors(inner.cur_node_id, |&id| inner.nodes[idx(id)].parent).count()
+    }
+
+    /// Returns the path of the file currently being read.
+    /// Macro and loop nodes are transparent, so this is the file they stem from.
+    fn cur_file(&self) -> Option<PathBuf> {
+        let inner = self.0.borrow();
+        let mut node_id = inner.cur_node_id;
+        while let Some(id) = node_id {
+            let node = &inner.nodes[idx(id)];
+            if let NodeKind::File(storage) = &node.kind {
+                return Some(PathBuf::from(storage.name().deref()));
+            }
+            node_id = node.parent;
+        }
+        None
+    }
+
+    /// Returns the directory containing the file currently being read.
+    fn cur_dir(&self) -> Option<PathBuf> {
+        self.cur_file()?.parent().map(Path::to_path_buf)
+    }
+
+    /// What identifies a file regardless of how it was named: its canonical path, unless it comes
+    /// from a resolver, which is trusted to name each file in a single way.
+    fn file_key(&self, path: PathBuf) -> PathBuf {
+        match &self.0.borrow().includes {
+            Includes::Paths(_) => path.canonicalize().unwrap_or(path),
+            Includes::Resolver(_) => path,
+        }
+    }
+
+    /// Makes further `INCLUDE`s of the file currently being read do nothing; this is `ONCE`.
+    pub fn include_once(&self) {
+        if let Some(path) = self.cur_file() {
+            let key = self.file_key(path);
+            self.0.borrow_mut().once_files.insert(key);
+        }
+    }
+
+    /// Whether `INCLUDE`ing `path` should be skipped, because that file used `ONCE`.
+    pub fn is_included_once(&self, path: &str) -> bool {
+        let full_path = match &self.0.borrow().includes {
+            Includes::Paths(_) => None,
+            Includes::Resolver(_) => Some(PathBuf::from(path)),
+        };
+        let Some(full_path) = full_path.or_else(|| self.find_include(path)) else {
+            return false;
+        };
+        let key = self.file_key(full_path);
+        self.0.borrow().once_files.contains(&key)
+    }
+
+    /// Looks for a file to be `INCLUDE`d.
+    ///
+    /// Relative paths are first looked up relative to the including file's directory, then in each
+    /// of the include paths, in order. Absolute paths bypass the search entirely.
+    pub fn find_include(&self, path: &str) -> Option<PathBuf> {
+        let path = Path::new(path);
+        if path.is_absolute() {
+            return Some(path.to_path_buf());
+        }
+
+        let cur_dir = self.cur_dir();
+        let inner = self.0.borrow();
+        let include_paths = match &inner.includes {
+            Includes::Paths(paths) => paths.as_slice(),
+            Includes::Resolver(_) => &[],
+        };
+        cur_dir
+            .iter()
+            .chain(include_paths)
+            .map(|dir| dir.join(path))
+            .find(|candidate| candidate.is_file())
+    }
+
+    /// Reads a file to be `INCLUDE`d, or returns `None` if it cannot be found.
+    pub fn read_include(&self, path: &str) -> Option<io::Result<Storage>> {
+        if let Includes::Resolver(resolver) = &self.0.borrow().includes {
+            return resolver(path).map(|contents| {
+                Storage::from_readable(path.to_string().into(), contents.as_bytes())
+            });
+        }
+
+        let full_path = self.find_include(path)?;
+        let encoding = self.0.borrow().encoding;
+        Some(File::open(&full_path).and_then(|file| {
+            Storage::from_file(full_path.display().to_string().into(), &file, encoding)
+        }))
+    }
+
+    /// Reads a file to be `INCBIN`ed, or returns `None` if it cannot be found.
+    pub fn read_binary(&self, path: &str) -> Option<io::Result<Binary>> {
+        if let Includes::Resolver(resolver) = &self.0.borrow().includes {
+            return resolver(path).map(|contents| Ok(Binary::Read(contents.into_bytes())));
+        }
+
+        let full_path = self.find_include(path)?;
+        Some(File::open(full_path).and_then(|file| Binary::from_file(&file)))
+    }
+
     pub fn push_file(&self, storage: Rc<Storage>, lexer: &mut Lexer) {
         self.push_new_node(NodeKind::File(storage));
         lexer.push_new_state();
@@ -169,11 +370,63 @@ impl Fstack {
         lexer.push_new_state();
     }
 
-    pub fn handle_end_of_node(&self, lexer: &mut Lexer) {
-        // TODO: handle looping for loop nodes (and reset the lexer state as well!)
+    /// Enters a loop's first iteration; the loop must run at least once.
+    /// The caller is responsible for setting the `FOR` variable (if any) to its initial value.
+    pub fn push_loop(
+        &self,
+        body: Rc<SourceString>,
+        nb_iterations: u32,
+        for_var: Option<ForVar>,
+        lexer: &mut Lexer,
+    ) {
+        debug_assert_ne!(nb_iterations, 0);
+        self.push_new_node(NodeKind::Loop(
+            body,
+            LoopState {
+                iteration: 1,
+                nb_iterations,
+                for_var,
+            },
+        ));
+        lexer.push_new_state();
+    }
+
+    /// Leaves the current node, unless it is a loop with iterations left, in which case the next one
+    /// starts. Then, if that loop is a `FOR`, this returns the variable's new value for the caller
+    /// to set.
+    pub fn handle_end_of_node(
+        &self,
+        lexer: &mut Lexer,
+        macro_args: &mut Vec<MacroArgs>,
+    ) -> Option<(SourceString, i32)> {
+        {
+            let mut inner = self.0.borrow_mut();
+            let id = idx(inner.cur_node_id.unwrap());
+            if let NodeKind::Loop(_, state) = &mut inner.nodes[id].kind {
+                if state.iteration < state.nb_iterations {
+                    state.iteration += 1;
+                    // Start reading the body over.
+                    lexer.pop_state();
+                    lexer.push_new_state();
+                    return state.for_var.as_mut().map(|var| {
+                        var.value = var.value.wrapping_add(var.step);
+                        (var.name.clone(), var.value)
+                    });
+                }
+            }
+        }
 
+        let is_macro = {
+            let inner = self.0.borrow();
+            let node = &inner.nodes[idx(inner.cur_node_id.unwrap())];
+            matches!(node.kind, NodeKind::Macro(..))
+        };
+        if is_macro {
+            macro_args.pop();
+        }
         self.pop_node();
         lexer.pop_state();
+        None
     }
 }
 
@@ -181,8 +434,7 @@ impl AsRef<str> for Node {
     fn as_ref(&self) -> &str {
         match &self.kind {
             NodeKind::File(storage) => storage.deref().as_ref(),
-            NodeKind::Macro(_, body) => body.as_ref(),
-            NodeKind::Loop(_) => todo!(),
+            NodeKind::Macro(_, body) | NodeKind::Loop(body, _) => body.as_ref(),
         }
     }
 }
@@ -201,16 +453,19 @@ impl Node {
     pub fn slice(&self, range: Range<usize>) -> SourceString {
         match &self.kind {
             NodeKind::File(storage) => SourceString::from_storage(Rc::clone(storage), range),
-            NodeKind::Macro(_, body) => SourceString::new_sliced(body, range),
-            NodeKind::Loop(_) => todo!(),
+            NodeKind::Macro(_, body) | NodeKind::Loop(body, _) => {
+                SourceString::new_sliced(body, range)
+            }
         }
     }
 
     pub fn storage_base_ofs(&self) -> usize {
         match &self.kind {
             NodeKind::File(_) => 0,
-            NodeKind::Macro(_, body) => SourceString::storage_base_ofs(body).unwrap_or(0), // The offset doesn't really matter if there is no storage.
-            NodeKind::Loop(_) => todo!(),
+            // The offset doesn't really matter if there is no storage.
+            NodeKind::Macro(_, body) | NodeKind::Loop(body, _) => {
+                SourceString::storage_base_ofs(body).unwrap_or(0)
+            }
         }
     }
 }
@@ -231,7 +486,11 @@ impl Drop for NodeHandle<'_> {
     }
 }
 
-impl NodeHandle<'_> {
+impl<'fstack> NodeHandle<'fstack> {
+    pub fn fstack(&self) -> &'fstack Fstack {
+        self.fstack
+    }
+
     pub fn with_node<T, F: FnOnce(&Node) -> T>(&self, f: F) -> T {
         let inner = self.fstack.0.borrow();
         f(&inner.nodes[self.node_id])
@@ -243,8 +502,9 @@ impl Node {
     fn storage(&self) -> Option<&Storage> {
         match &self.kind {
             NodeKind::File(storage) => Some(storage),
-            NodeKind::Macro(_, string) => SourceString::storage(string).map(Deref::deref),
-            NodeKind::Loop(_) => todo!(),
+            NodeKind::Macro(_, string) | NodeKind::Loop(string, _) => {
+                SourceString::storage(string).map(Deref::deref)
+            }
         }
     }
 }
@@ -262,6 +522,28 @@ impl Binder<'_> {
             None => Err(codespan_reporting::files::Error::FileMissing),
         }
     }
+
+    /// Describes the chain of nodes leading to the given one, innermost first.
+    pub fn backtrace(&self, id: usize) -> Vec<Frame<'_>> {
+        std::iter::successors(self.0.nodes.get(id), |node| {
+            node.parent.map(|parent| &self.0.nodes[idx(parent)])
+        })
+        .map(|node| match &node.kind {
+            NodeKind::File(storage) => Frame::File(storage.name()),
+            NodeKind::Macro(..) => Frame::Macro,
+            NodeKind::Loop(_, state) => Frame::Loop(state.iteration),
+        })
+        .collect()
+    }
+}
+
+/// One level of a [`Binder::backtrace`].
+#[derive(Debug, Clone, Copy)]
+pub enum Frame<'fstack> {
+    File(&'fstack SourceString),
+    // TODO: macros don't remember their names yet
+    Macro,
+    Loop(u32),
 }
 
 impl<'fstack> Files<'fstack> for Binder<'fstack> {
@@ -309,3 +591,125 @@ impl<'fstack> Files<'fstack> for Binder<'fstack> {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn storage(path: &Path) -> Rc<Storage> {
+        Rc::new(
+            Storage::from_readable(path.display().to_string().into(), &b""[..])
+                .expect("Reading from a slice cannot fail"),
+        )
+    }
+
+    #[test]
+    fn include_resolution() {
+        let root = std::env::temp_dir().join(format!("rgbasm-include-{}", std::process::id()));
+        let dir = root.join("dir");
+        let sub_dir = dir.join("sub");
+        fs::create_dir_all(&sub_dir).unwrap();
+        for path in ["a.asm", "b.asm", "sub/c.asm", "sub/d.asm"] {
+            fs::write(dir.join(path), "").unwrap();
+        }
+
+        let fstack = Fstack::new(storage(&dir.join("a.asm")), vec![dir.clone()]);
+        // `dir/a.asm` including `b.asm` must pick up its sibling.
+        assert_eq!(fstack.find_include("b.asm"), Some(dir.join("b.asm")));
+
+        // A file two levels deep resolves its own siblings first...
+        let mut lexer = Lexer::new();
+        fstack.push_file(storage(&sub_dir.join("c.asm")), &mut lexer);
+        assert_eq!(fstack.find_include("d.asm"), Some(sub_dir.join("d.asm")));
+        // ...and falls back to the include paths otherwise.
+        assert_eq!(fstack.find_include("b.asm"), Some(dir.join("b.asm")));
+        assert_eq!(fstack.find_include("e.asm"), None);
+
+        // Absolute paths are not searched for.
+        let abs_path = root.join("nonexistent.asm");
+        assert_eq!(
+            fstack.find_include(&abs_path.display().to_string()),
+            Some(abs_path)
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn include_once() {
+        let result = crate::asm::assemble::assemble_str(
+            // `ONCE` is only special as a directive, so it can also be a symbol's name.
+            "DEF once EQU 1\nINCLUDE \"guarded.inc\"\nINCLUDE \"guarded.inc\"\nINCLUDE \"./guarded.inc\"\nhi\n",
+            &Default::default(),
+            |path| match path {
+                "guarded.inc" | "./guarded.inc" => {
+                    Some("ONCE\nMACRO hi\nPRINTLN \"hi\"\nENDM\n".into())
+                }
+                _ => None,
+            },
+            |_, _| (),
+        );
+        // The resolver does not know that both names are the same file.
+        assert_eq!(result.diagnostics.len(), 1, "{:?}", result.diagnostics);
+        assert!(result.diagnostics[0].starts_with("error: hi is already defined"));
+        assert_eq!(result.printed, "hi\n");
+
+        // On the filesystem however, files are identified by their canonical path.
+        let dir = std::env::temp_dir().join(format!("rgbasm-once-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        let fstack = Fstack::new(storage(&dir.join("main.asm")), vec![]);
+        let mut lexer = Lexer::new();
+        fstack.push_file(storage(&dir.join("sub/../header.inc")), &mut lexer);
+        fs::write(dir.join("header.inc"), "").unwrap();
+        assert!(!fstack.is_included_once("header.inc"));
+        fstack.include_once();
+        assert!(fstack.is_included_once("header.inc"));
+        assert!(fstack.is_included_once(&dir.join("header.inc").display().to_string()));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn display_locations() {
+        let (_, begin) = crate::asm::test_utils::assemble_with(
+            "loc.asm",
+            "; Sections\n  SECTION \"a\", ROM0\n",
+            |_, _| {},
+            |sections| {
+                sections
+                    .get_by_name("a")
+                    .unwrap()
+                    .definition()
+                    .0
+                    .to_string()
+            },
+        );
+        assert_eq!(begin, "loc.asm:2:3");
+        assert_eq!(Location::builtin().to_string(), "<builtin>");
+    }
+
+    #[test]
+    fn latin1_input() {
+        let path = std::env::temp_dir().join(format!("rgbasm-latin1-{}.asm", std::process::id()));
+        fs::write(&path, b"; caf\xE9 \xABcr\xE8me\xBB\n  nop\n").unwrap();
+        let file = File::open(&path).unwrap();
+        let read =
+            |encoding| Storage::from_file(path.display().to_string().into(), &file, encoding);
+
+        let err = read(Encoding::Utf8).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "invalid UTF-8 at byte offset 5");
+
+        let storage = read(Encoding::Latin1).unwrap();
+        assert_eq!(storage.as_ref(), "; café «crème»\n  nop\n");
+        // Columns after the comment must not be thrown off by the re-encoded characters.
+        let nop_ofs = storage.as_ref().find("nop").unwrap();
+        let fstack = Fstack::new(Rc::new(storage), vec![]);
+        let location = fstack.get_files().location(0, nop_ofs).unwrap();
+        assert_eq!((location.line_number, location.column_number), (2, 3));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -9,8 +9,68 @@ use std::{
     rc::Rc,
 };
 
+use clap::ValueEnum;
 use memmap2::{Mmap, MmapOptions};
 
+/// How source files' bytes are decoded into text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Encoding {
+    /// Invalid sequences are an error.
+    #[default]
+    #[value(name = "utf-8", alias = "utf8")]
+    Utf8,
+    /// Every byte is the Unicode code point of the same value; no sequence is invalid.
+    #[value(name = "latin-1", alias = "latin1", alias = "iso-8859-1")]
+    Latin1,
+}
+
+impl Encoding {
+    /// Decodes `bytes`, borrowing them if they are already valid UTF-8 text with the same meaning.
+    fn decode(self, bytes: &[u8]) -> io::Result<Cow<'_, str>> {
+        match self {
+            Self::Utf8 => std::str::from_utf8(bytes)
+                .map(Cow::Borrowed)
+                .map_err(|err| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid UTF-8 at byte offset {}", err.valid_up_to()),
+                    )
+                }),
+            // ASCII is the only part of Latin-1 that is encoded the same way in UTF-8.
+            Self::Latin1 if bytes.is_ascii() => Ok(Cow::Borrowed(
+                std::str::from_utf8(bytes).expect("ASCII is valid UTF-8"),
+            )),
+            Self::Latin1 => Ok(Cow::Owned(
+                bytes.iter().map(|&byte| char::from(byte)).collect(),
+            )),
+        }
+    }
+
+    /// Encodes `text` back into the bytes it was decoded from.
+    /// Characters that the encoding cannot represent (which can only come from outside source
+    /// files, e.g. file names) are encoded as UTF-8.
+    pub fn encode(self, text: &str) -> Cow<'_, [u8]> {
+        match self {
+            Self::Latin1 if !text.is_ascii() => Cow::Owned(
+                text.chars()
+                    .flat_map(|c| {
+                        let mut buf = [0; 4];
+                        let len = match u8::try_from(c) {
+                            Ok(byte) => {
+                                buf[0] = byte;
+                                1
+                            }
+                            Err(_) => c.encode_utf8(&mut buf).len(),
+                        };
+                        buf.into_iter().take(len)
+                    })
+                    .collect(),
+            ),
+            Self::Utf8 | Self::Latin1 => Cow::Borrowed(text.as_bytes()),
+        }
+    }
+}
+
 pub struct Storage {
     name: SourceString,
     bytes: StorageKind,
@@ -37,24 +97,33 @@ impl Storage {
         }
     }
 
-    pub fn from_file(name: SourceString, file: &File) -> io::Result<Self> {
+    pub fn from_file(name: SourceString, file: &File, encoding: Encoding) -> io::Result<Self> {
         // FIXME: we will get a lot of UB if the file changes under us and yields some invalid UTF-8...
         // TODO: compare the performance with and without
         match unsafe { MmapOptions::new().populate().map(file) } {
-            Ok(mapping) => Ok(match String::from_utf8_lossy(&mapping) {
+            Ok(mapping) => Ok(match encoding.decode(&mapping)? {
                 Cow::Borrowed(_) => Self::new(name, StorageKind::Mapped(mapping)),
                 Cow::Owned(string) => Self::new(name, StorageKind::Read(string)),
             }),
-            Err(_) => Self::from_readable(name, file),
+            Err(_) => Self::from_readable_with(name, file, encoding),
         }
     }
 
-    pub fn from_readable<R: Read>(name: SourceString, mut src: R) -> io::Result<Self> {
+    /// Reads UTF-8 source code.
+    pub fn from_readable<R: Read>(name: SourceString, src: R) -> io::Result<Self> {
+        Self::from_readable_with(name, src, Encoding::Utf8)
+    }
+
+    pub fn from_readable_with<R: Read>(
+        name: SourceString,
+        mut src: R,
+        encoding: Encoding,
+    ) -> io::Result<Self> {
         let mut data = Vec::new();
         src.read_to_end(&mut data)?;
         Ok(Self::new(
             name,
-            StorageKind::Read(match String::from_utf8_lossy(&data) {
+            StorageKind::Read(match encoding.decode(&data)? {
                 Cow::Borrowed(_) => unsafe { String::from_utf8_unchecked(data) },
                 Cow::Owned(string) => string,
             }),
@@ -76,7 +145,7 @@ impl Storage {
     }
 
     pub(crate) fn line_start(&self, byte_index: usize) -> Result<usize, usize> {
-        if byte_index < self.bytes.len() {
+        if byte_index <= self.bytes.len() {
             Ok(
                 self.with_line_starts(|line_starts| match line_starts.binary_search(&byte_index) {
                     Ok(line) => line,
@@ -118,6 +187,43 @@ impl Debug for Storage {
     }
 }
 
+/// The contents of an `INCBIN`ed file; only the requested slice of it is copied into the section.
+#[derive(Debug)]
+pub enum Binary {
+    /// The file is mapped straight into memory, so that large files are not read in full.
+    Mapped(Mmap),
+    /// The file is read into memory, usually because mapping failed (e.g. because it is empty).
+    Read(Vec<u8>),
+}
+
+impl Binary {
+    pub fn from_file(file: &File) -> io::Result<Self> {
+        // FIXME: if the file is truncated while mapped, reading the mapping raises `SIGBUS`.
+        // The mapping is only read right after being created, which narrows that window.
+        match unsafe { Mmap::map(file) } {
+            Ok(mapping) => Ok(Self::Mapped(mapping)),
+            Err(_) => Self::from_readable(file),
+        }
+    }
+
+    pub fn from_readable<R: Read>(mut src: R) -> io::Result<Self> {
+        let mut data = Vec::new();
+        src.read_to_end(&mut data)?;
+        Ok(Self::Read(data))
+    }
+}
+
+impl Deref for Binary {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Mapped(mapping) => mapping,
+            Self::Read(data) => data,
+        }
+    }
+}
+
 impl AsRef<str> for Storage {
     fn as_ref(&self) -> &str {
         self.bytes.deref()
@@ -149,6 +255,12 @@ enum SourceStringImpl {
     Owned(String),
 }
 
+impl Default for SourceString {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SourceString {
     /// Creates a new, empty `SourceString`.
     /// This does not allocate.
@@ -222,7 +334,9 @@ impl SourceString {
             }
             inner @ SourceStringImpl::Owned(_) => inner,
         });
-        let SourceStringImpl::Owned(string) = &mut this.0 else { unreachable!(); };
+        let SourceStringImpl::Owned(string) = &mut this.0 else {
+            unreachable!();
+        };
         string
     }
 
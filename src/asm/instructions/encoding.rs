@@ -3,10 +3,10 @@ use std::{
     ops::Deref,
 };
 
-use rgbds::RelocKind;
+use crate::RelocKind;
 
 use super::{Instruction, PrefixKind};
-use crate::{expr::ByteOrExpr, language::ParseError};
+use crate::asm::{expr::ByteOrExpr, language::ParseError};
 
 #[derive(Debug)]
 pub struct Encoder<'fstack>(EncoderStorage<ByteOrExpr<'fstack>>, usize);
@@ -3,7 +3,7 @@ use std::{fmt::Display, ops::Not};
 use codespan_reporting::diagnostic::Diagnostic;
 use parse_display::Display;
 
-use crate::expr::Expression;
+use crate::asm::expr::Expression;
 
 mod encoding;
 pub use encoding::Encoder;
@@ -174,6 +174,20 @@ impl<'fstack> Instruction<'fstack> {
         }
     }
 
+    /// `ldi` (if `inc`) or `ldd`, which are `ld [hl+]` or `ld [hl-]` with the `[hl]` written plainly.
+    pub fn ld_hl_inc_dec(inc: bool, dest: Reg8, src: Reg8) -> InstrResult<Self> {
+        let (reg, mnemonic) = if inc {
+            (Reg16Ind::Hli, "ldi")
+        } else {
+            (Reg16Ind::Hld, "ldd")
+        };
+        match (dest, src) {
+            (Reg8::HlInd, src) => Self::ld_reg16_ind_a(reg, src),
+            (dest, Reg8::HlInd) => Self::ld_a_reg16_ind(dest, reg),
+            _ => Err(BadInstructionKind::LdHlIncDec(mnemonic)),
+        }
+    }
+
     pub fn cpl(reg: Option<Reg8>) -> InstrResult<Self> {
         match reg {
             None | Some(Reg8::A) => Ok(Self::Cpl),
@@ -229,7 +243,7 @@ impl<'fstack> Instruction<'fstack> {
         match (dest, src) {
             (Reg8::C, Reg8::A) => Ok(Instruction::LdhCA),
             (Reg8::C, src) => Err(BadInstructionKind::LdhToC(src)),
-            _ => todo!(),
+            (ofs, _) => Err(BadInstructionKind::CIndOfs(ofs)),
         }
     }
 
@@ -244,7 +258,7 @@ impl<'fstack> Instruction<'fstack> {
         match (dest, src) {
             (Reg8::A, Reg8::C) => Ok(Instruction::LdhAC),
             (dest, Reg8::C) => Err(BadInstructionKind::LdhFromC(dest)),
-            _ => todo!(),
+            (_, ofs) => Err(BadInstructionKind::CIndOfs(ofs)),
         }
     }
 
@@ -281,6 +295,8 @@ pub enum BadInstructionKind {
     Cpl(Reg8),
     #[display("`ld [hl], [hl]` does not exist")]
     LdHlHl,
+    #[display("`{0}` only exists as `{0} [hl], a` and `{0} a, [hl]`")]
+    LdHlIncDec(&'static str),
     #[display("the left-hand register of `{0}` must be `a`")]
     Alu(&'static str, Reg8, Reg8),
     #[display("`ldh` only supports `a`")]
@@ -307,6 +323,8 @@ pub enum BadInstructionKind {
     LdhToC(Reg8),
     #[display("only `ldh a, [c]` exists")]
     LdhFromC(Reg8),
+    #[display("only `c` can be added to `$ff00`, not `{0}`")]
+    CIndOfs(Reg8),
     #[display("the left-hand register of `{0}` must be `a`")]
     AluImm(&'static str, Reg8),
 
@@ -1,9 +1,9 @@
-use std::{cell::RefCell, dbg, debug_assert, debug_assert_eq, ops::Deref, rc::Rc};
+use std::{cell::RefCell, dbg, debug_assert, debug_assert_eq, fmt::Display, ops::Deref, rc::Rc};
 
-use crate::{
-    error::Reporter,
+use crate::asm::{
+    error::{Reporter, WarningFlag},
     fstack::{Fstack, Node, NodeHandle},
-    input::SourceString,
+    input::{Encoding, SourceString},
     language::{tokens::can_start_ident, Warning},
     macro_args::MacroArgs,
     symbols::Symbols,
@@ -23,6 +23,8 @@ pub struct Lexer {
     gfx_digits: [char; 4],
     pub expand_equs: bool,
     pub mode: Mode,
+    /// Set when the file ended while capturing a body, i.e. its closing keyword is missing.
+    pub unterminated_capture: bool,
 }
 
 #[derive(Debug)]
@@ -48,6 +50,12 @@ pub enum Mode {
     CaptureLoopBody,
 }
 
+impl Default for Lexer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Lexer {
     pub fn new() -> Self {
         Self {
@@ -56,6 +64,7 @@ impl Lexer {
             gfx_digits: ['0', '1', '2', '3'],
             expand_equs: true, // Enabled by default.
             mode: Mode::Normal,
+            unterminated_capture: false,
         }
     }
 
@@ -112,6 +121,9 @@ pub struct Location<'fstack> {
     offset: usize,
 }
 
+/// The locations of the beginning and end of something, e.g. a token.
+pub type Span<'fstack> = (Location<'fstack>, Location<'fstack>);
+
 impl Location<'_> {
     pub(crate) const fn builtin() -> Location<'static> {
         Location {
@@ -129,6 +141,34 @@ impl Location<'_> {
     }
 }
 
+impl<'fstack> Location<'fstack> {
+    /// Returns the smallest span covering both `primary` and `other`, e.g. to report an error
+    /// about several tokens at once.
+    /// If they are not in the same file (or expansion), `primary` is returned unchanged.
+    pub fn merge(primary: Span<'fstack>, other: Span<'fstack>) -> Span<'fstack> {
+        if primary.0.storage != other.0.storage || primary.1.storage != other.1.storage {
+            return primary;
+        }
+
+        let begin = std::cmp::min_by_key(primary.0, other.0, |loc| loc.offset);
+        let end = std::cmp::max_by_key(primary.1, other.1, |loc| loc.offset);
+        (begin, end)
+    }
+}
+
+/// Formats as `file:line:col`; locations not tied to any file are shown as `<builtin>`.
+impl Display for Location<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.storage {
+            Some(handle) => handle
+                .fstack()
+                .display_diag_info(&Fstack::make_diag_info(self, None))
+                .fmt(f),
+            None => f.write_str("<builtin>"),
+        }
+    }
+}
+
 impl PartialOrd for Location<'_> {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         if self.storage != other.storage {
@@ -183,6 +223,63 @@ fn escape_char(c: char, is_macro_arg: bool) -> Option<char> {
     }
 }
 
+/// A string literal's value.
+///
+/// `\xNN` escapes stand for a byte, not a character, so they must bypass both the charmap and the
+/// source encoding; they are thus kept apart from the text, alongside the offset they occur at.
+#[derive(Debug, Clone)]
+pub struct StringValue {
+    pub text: SourceString,
+    /// (Offset into `text`, byte) pairs, in increasing offset order.
+    raw_bytes: Vec<(usize, u8)>,
+}
+
+impl Default for StringValue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StringValue {
+    pub fn new() -> Self {
+        SourceString::new().into()
+    }
+
+    pub fn has_raw_bytes(&self) -> bool {
+        !self.raw_bytes.is_empty()
+    }
+
+    pub fn concat(&mut self, other: &Self) {
+        let ofs = self.text.len();
+        SourceString::concat(&mut self.text, &other.text);
+        self.raw_bytes
+            .extend(other.raw_bytes.iter().map(|&(byte_ofs, byte)| (ofs + byte_ofs, byte)));
+    }
+
+    /// Encodes the string into bytes: the text is encoded the way the source was, and raw bytes
+    /// are inserted as-is.
+    pub fn bytes(&self, encoding: Encoding) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.text.len() + self.raw_bytes.len());
+        let mut text_ofs = 0;
+        for &(byte_ofs, byte) in &self.raw_bytes {
+            bytes.extend_from_slice(&encoding.encode(&self.text[text_ofs..byte_ofs]));
+            bytes.push(byte);
+            text_ofs = byte_ofs;
+        }
+        bytes.extend_from_slice(&encoding.encode(&self.text[text_ofs..]));
+        bytes
+    }
+}
+
+impl From<SourceString> for StringValue {
+    fn from(text: SourceString) -> Self {
+        Self {
+            text,
+            raw_bytes: Vec::new(),
+        }
+    }
+}
+
 macro_rules! line_cont_start {
     () => {
         ' ' | '\r' | '\n'
@@ -264,11 +361,8 @@ impl<'fstack> Tokenizer<'fstack, '_, '_, '_, '_> {
                 self.with_active_macro_args(|args| Ok(args.make_concat())),
                 2,
             ),
-            '<' => {
-                todo!();
-            }
-
-            '@' => (todo!(), 2),
+            '<' => (Err(AsmErrorKind::Unsupported("`\\<...>` macro argument syntax")), 2),
+            '@' => (Err(AsmErrorKind::Unsupported("`\\@`")), 2),
 
             _ => return None,
         })
@@ -341,11 +435,17 @@ impl<'fstack> Tokenizer<'fstack, '_, '_, '_, '_> {
                     Some('\\') if self.expand_macro_args => {
                         match self.read_putative_backslash_expansion(chars) {
                             Some((Ok(expansion), trigger_len)) => {
-                                self.macro_arg_scan_distance += trigger_len; // Macro args aren't recursive.
+                                // The trigger is not part of any capture, even if the expansion is empty.
+                                if let Some((_, capture_disrupted)) = self.capture.as_mut() {
+                                    *capture_disrupted = true;
+                                }
 
-                                // Don't bother doing the expensive work for empty expansions.
-                                if !expansion.is_empty() {
-                                    self.macro_arg_scan_distance += expansion.len();
+                                // Don't bother doing the expensive work for empty expansions:
+                                // the trigger can simply be skipped, as if it expanded to nothing.
+                                if expansion.is_empty() {
+                                    *cur_offset += trigger_len;
+                                } else {
+                                    self.macro_arg_scan_distance += trigger_len + expansion.len(); // Macro args aren't recursive.
                                     Self::begin_expansion(&mut lexer, expansion, trigger_len);
                                 }
                                 continue;
@@ -366,8 +466,12 @@ impl<'fstack> Tokenizer<'fstack, '_, '_, '_, '_> {
                                     AsmError::new(begin, end, kind).into(),
                                 );
 
-                                // Skip the bad expansion trigger.
+                                // Skip the bad expansion trigger, and look at what comes after it instead.
                                 *cur_offset += trigger_len;
+                                if let Some((_, capture_disrupted)) = self.capture.as_mut() {
+                                    *capture_disrupted = true;
+                                }
+                                continue;
                             }
 
                             None => {}
@@ -375,7 +479,28 @@ impl<'fstack> Tokenizer<'fstack, '_, '_, '_, '_> {
                         Some('\\') // If it doesn't introduce a macro arg, then just return it.
                     }
                     Some('{') if self.enable_interpolation => {
-                        todo!();
+                        // Skip the whole interpolation, so that its contents are not lexed on their own.
+                        let len = source
+                            .find(['}', '\n'])
+                            .map_or(source.len(), |idx| idx + usize::from(source[idx..].starts_with('}')));
+                        let cur_node = self.fstack.cur_node_handle();
+                        let begin = Location {
+                            storage: cur_node.clone(),
+                            offset: *cur_offset,
+                        };
+                        let end = Location {
+                            storage: cur_node,
+                            offset: *cur_offset + len,
+                        };
+                        self.reporter.borrow_mut().report_error(
+                            self.fstack,
+                            AsmError::new(begin, end, AsmErrorKind::Unsupported("Symbol interpolation")).into(),
+                        );
+                        *cur_offset += len;
+                        if let Some((_, capture_disrupted)) = self.capture.as_mut() {
+                            *capture_disrupted = true;
+                        }
+                        continue;
                     }
                     Some(c) => Some(c),
                     None => None,
@@ -418,6 +543,10 @@ impl<'fstack> Tokenizer<'fstack, '_, '_, '_, '_> {
 
         let c = bumped_char.expect("Cannot shift at EOF!?");
         *cur_ofs += skip + c.len_utf8();
+        // The characters are no longer ahead, so any macro args after them must be expanded.
+        self.macro_arg_scan_distance = self
+            .macro_arg_scan_distance
+            .saturating_sub(skip + c.len_utf8());
         c
     }
 
@@ -511,6 +640,13 @@ impl Tokenizer<'_, '_, '_, '_, '_> {
         self.expand_macro_args = false;
         self.enable_interpolation = false;
 
+        let ignored = self.with_cur_source(|source, _| WarningFlag::parse_ignore_comment(source));
+        if let Some(ids) = ignored {
+            let loc = self.cur_loc();
+            self.reporter
+                .borrow_mut()
+                .ignore_on_next_line(self.fstack, &loc, &ids);
+        }
         while !matches!(self.peek(), None | Some('\r' | '\n')) {
             self.bump();
         }
@@ -548,7 +684,7 @@ impl Tokenizer<'_, '_, '_, '_, '_> {
                     Warning {
                         begin,
                         end,
-                        kind: crate::language::WarningKind::NestedBlockComment,
+                        kind: crate::asm::language::WarningKind::NestedBlockComment,
                     },
                 );
             } else if ch == '*' && self.peek() == Some('/') {
@@ -563,8 +699,40 @@ impl Tokenizer<'_, '_, '_, '_, '_> {
         res
     }
 
+    /// Discards the rest of a line continuation, whose backslash has already been shifted.
     fn discard_line_cont(&mut self) -> Result<(), AsmErrorKind> {
-        todo!(); // I'm thinking, grab the current source, and try to read from it to the end. This bypasses both expansion kinds, and ensures that an `equs` or macro arg cannot begin a line continuation.
+        // Neither kind of expansion may be part of a line continuation.
+        let prev_state = (self.expand_macro_args, self.enable_interpolation);
+        self.expand_macro_args = false;
+        self.enable_interpolation = false;
+
+        let res = loop {
+            match self.peek() {
+                Some(c) if is_whitespace(c) => {
+                    self.bump_internal();
+                }
+                Some(';') => {
+                    while !matches!(self.peek(), None | Some('\r' | '\n')) {
+                        self.bump_internal();
+                    }
+                }
+                Some(c @ ('\r' | '\n')) => {
+                    self.bump_internal();
+                    if c == '\r' && self.peek() == Some('\n') {
+                        self.bump_internal();
+                    }
+                    break Ok(());
+                }
+                Some(c) => break Err(AsmErrorKind::BadLineCont(c)),
+                None => break Err(AsmErrorKind::LineContEof),
+            }
+        };
+
+        (self.expand_macro_args, self.enable_interpolation) = prev_state;
+        if let Some((_, capture_disrupted)) = self.capture.as_mut() {
+            *capture_disrupted = true;
+        }
+        res
     }
 
     fn read_anon_label_ref(&mut self, first_char: char) -> u32 {
@@ -578,27 +746,55 @@ impl Tokenizer<'_, '_, '_, '_, '_> {
         n
     }
 
-    fn read_number(&mut self, base_value: u32, radix: u32) -> u32 {
-        let mut value = base_value;
+    /// Reads digits, which may be separated by single underscores; the caller must have checked
+    /// that the number does not start with one. Misplaced separators are reported, but otherwise
+    /// ignored like the others.
+    fn read_digits<F: Fn(char) -> Option<u32>, G: FnMut(u32)>(&mut self, digit: F, mut push: G) {
+        // Where the latest run of separators started, and how long it is.
+        let mut separators = None;
         while let Some(ch) = self.peek() {
             if ch == '_' {
-                // Separator character, ignore.
-            } else if let Some(digit) = ch.to_digit(radix) {
-                value = value.wrapping_mul(radix).wrapping_add(digit);
+                let (_, nb_separators) = separators.get_or_insert_with(|| (self.cur_loc(), 0));
+                *nb_separators += 1;
+            } else if let Some(digit) = digit(ch) {
+                if let Some((begin, nb_separators)) = separators.take() {
+                    if nb_separators > 1 {
+                        self.report_separators(begin, AsmErrorKind::DoubleSeparator);
+                    }
+                }
+                push(digit);
             } else {
                 break;
             }
             self.bump();
         }
+        if let Some((begin, _)) = separators {
+            self.report_separators(begin, AsmErrorKind::TrailingSeparator);
+        }
+    }
+
+    fn report_separators(&mut self, begin: Location, kind: AsmErrorKind) {
+        let end = self.cur_loc();
+        self.reporter
+            .borrow_mut()
+            .report_error(self.fstack, AsmError::new(begin, end, kind).into());
+    }
+
+    fn read_number(&mut self, base_value: u32, radix: u32) -> u32 {
+        let mut value = base_value;
+        self.read_digits(
+            |ch| ch.to_digit(radix),
+            |digit| value = value.wrapping_mul(radix).wrapping_add(digit),
+        );
         value
     }
 
     fn read_bin_number(&mut self, first_char: char) -> u32 {
-        let lexer = self.lexer.borrow();
+        let bin_digits = self.lexer.borrow().bin_digits;
         let digit = |ch| {
-            if ch == lexer.bin_digits[0] {
+            if ch == bin_digits[0] {
                 Some(0)
-            } else if ch == lexer.bin_digits[1] {
+            } else if ch == bin_digits[1] {
                 Some(1)
             } else {
                 None
@@ -606,67 +802,56 @@ impl Tokenizer<'_, '_, '_, '_, '_> {
         };
 
         let mut value = digit(first_char).unwrap();
-        while let Some(ch) = self.peek() {
-            if ch == '_' {
-                // Separator character, ignore.
-            } else if let Some(digit) = digit(ch) {
-                value = value * 2 + digit;
-            } else {
-                break;
-            }
-            self.bump();
-        }
-
+        self.read_digits(digit, |digit| value = value.wrapping_mul(2).wrapping_add(digit));
         value
     }
 
     fn read_gfx_constant(&mut self) -> Result<u32, AsmErrorKind> {
-        let lexer = self.lexer.borrow();
+        // Copied so that the lexer is not borrowed while peeking.
+        let gfx_digits = self.lexer.borrow().gfx_digits;
         let digit = |ch| match ch {
-            ch if ch == lexer.gfx_digits[0] => Some(0),
-            ch if ch == lexer.gfx_digits[1] => Some(1),
-            ch if ch == lexer.gfx_digits[2] => Some(2),
-            ch if ch == lexer.gfx_digits[3] => Some(3),
+            ch if ch == gfx_digits[0] => Some(0),
+            ch if ch == gfx_digits[1] => Some(1),
+            ch if ch == gfx_digits[2] => Some(2),
+            ch if ch == gfx_digits[3] => Some(3),
             _ => None,
         };
 
+        // Separators are not allowed before the first digit.
+        if self.peek().and_then(digit).is_none() {
+            return Err(AsmErrorKind::NoGfxChars(gfx_digits));
+        }
         let mut msb = 0u8;
         let mut lsb = 0u8;
         let mut width = 0;
-        while let Some(ch) = self.peek() {
-            if ch == '_' {
-                if width == 0 {
-                    break; // This is not allowed as the first char.
-                }
-                // Separator character, ignore it.
-            } else if let Some(digit) = digit(ch) {
-                if width < 8 {
-                    msb = msb << 1 | digit & 1;
-                    lsb = lsb << 1 | digit >> 1;
-                }
-                if width < 9 {
-                    width += 1;
-                }
-            } else {
-                break;
+        self.read_digits(digit, |digit| {
+            if width < 8 {
+                msb = msb << 1 | (digit & 1) as u8;
+                lsb = lsb << 1 | (digit >> 1) as u8;
             }
-            self.bump();
-        }
+            if width < 9 {
+                width += 1;
+            }
+        });
 
-        if width == 0 {
-            return Err(AsmErrorKind::NoGfxChars(lexer.gfx_digits));
-        }
         if width > 8 {
             // TODO: warning
         }
         Ok(u32::from(msb) << 8 | u32::from(lsb))
     }
 
-    fn read_string_body(&mut self, multiline: bool) -> Result<(), ()> {
+    /// Reads a string literal's contents into the capture, and the bytes of its `\xNN` escapes into
+    /// `raw_bytes`; if that is `None`, the escapes are kept as-is instead, to be read again later.
+    fn read_string_body(
+        &mut self,
+        multiline: bool,
+        mut raw_bytes: Option<&mut Vec<(usize, u8)>>,
+    ) -> Result<(), ()> {
         loop {
+            // For characters that are not (or no longer) at the capture's end in the source code.
             macro_rules! append {
                 ($ch:expr) => {
-                    SourceString::push(&mut self.capture.as_mut().unwrap().0, $ch);
+                    SourceString::make_owned(&mut self.capture.as_mut().unwrap().0).push($ch);
                 };
             }
 
@@ -694,29 +879,96 @@ impl Tokenizer<'_, '_, '_, '_, '_> {
 
                 // Special characters.
                 Some('\\') => {
+                    let begin = self.cur_loc();
                     // Do not bump the backslash yet, as that might change the active expansion.
-                    let mut lexer = self.lexer.borrow_mut();
-                    let cur_state = lexer.cur_state_mut();
-                    let cur_node = self.cur_node_handle();
-                    cur_node.with_node(|node| {
-                        let (source, cur_ofs) = Self::get_state_source(cur_state, node);
-
-                        // Since the backslash hasn't been bumped, `source` points to it.
-                        debug_assert_eq!(source.chars().next(), Some('\\'));
-                        if let Some((result, trigger_len)) =
-                            self.read_putative_backslash_expansion(source[1..].chars())
-                        {
-                            *cur_ofs += trigger_len;
-                            match result {
-                                Ok(expansion) => {
-                                    let string = &mut self.capture.as_mut().unwrap().0;
-                                    SourceString::make_owned(string).push_str(&expansion);
+                    let expansion = {
+                        let mut lexer = self.lexer.borrow_mut();
+                        let cur_state = lexer.cur_state_mut();
+                        let cur_node = self.cur_node_handle();
+                        cur_node.with_node(|node| {
+                            let (source, cur_ofs) = Self::get_state_source(cur_state, node);
+
+                            // Since the backslash hasn't been bumped, `source` points to it.
+                            debug_assert_eq!(source.chars().next(), Some('\\'));
+                            let expansion =
+                                self.read_putative_backslash_expansion(source[1..].chars());
+                            if let Some((_, trigger_len)) = &expansion {
+                                *cur_ofs += trigger_len;
+                            }
+                            expansion
+                        })
+                    };
+
+                    if let Some((result, trigger_len)) = expansion {
+                        match result {
+                            Ok(expansion) => {
+                                let string = &mut self.capture.as_mut().unwrap().0;
+                                SourceString::make_owned(string).push_str(&expansion);
+                            }
+                            Err(kind) => {
+                                let end = Location {
+                                    storage: begin.storage.clone(),
+                                    offset: begin.offset + trigger_len,
+                                };
+                                self.reporter.borrow_mut().report_error(
+                                    self.fstack,
+                                    AsmError::new(begin, end, kind).into(),
+                                );
+                                // The bad trigger has been skipped over.
+                                self.capture.as_mut().unwrap().1 = true;
+                            }
+                        }
+                    } else {
+                        // Regular ol' handling.
+                        self.bump_capture(false);
+
+                        match self.peek() {
+                            Some('\\' | '"' | '{' | '}') => self.bump_capture(true),
+                            Some('n') => {
+                                append!('\n');
+                                self.bump_capture(false);
+                            }
+                            Some('r') => {
+                                append!('\r');
+                                self.bump_capture(false);
+                            }
+                            Some('t') => {
+                                append!('\t');
+                                self.bump_capture(false);
+                            }
+                            Some('x') => {
+                                self.bump_capture(false);
+                                // Do not bump a non-digit character, it will then be read normally.
+                                let mut read_digit = || {
+                                    let digit = self.peek()?.to_digit(16)?;
+                                    self.bump_capture(false);
+                                    Some(digit as u8)
+                                };
+                                match read_digit().zip(read_digit()) {
+                                    Some((high, low)) => {
+                                        let byte = high << 4 | low;
+                                        let string = &mut self.capture.as_mut().unwrap().0;
+                                        match raw_bytes.as_deref_mut() {
+                                            Some(raw_bytes) => raw_bytes.push((string.len(), byte)),
+                                            None => SourceString::make_owned(string)
+                                                .push_str(&format!("\\x{byte:02X}")),
+                                        }
+                                    }
+                                    None => {
+                                        let end = self.cur_loc();
+                                        self.reporter.borrow_mut().report_error(
+                                            self.fstack,
+                                            AsmError::new(begin, end, AsmErrorKind::BadHexEscape)
+                                                .into(),
+                                        );
+                                    }
                                 }
-                                Err(kind) => {
-                                    let begin = self.cur_loc();
+                            }
+                            Some(line_cont_start!()) => {
+                                if let Err(kind) = self.discard_line_cont() {
                                     let end = Location {
                                         storage: begin.storage.clone(),
-                                        offset: begin.offset + trigger_len,
+                                        offset: self.cur_root_offset(),
                                     };
                                     self.reporter.borrow_mut().report_error(
                                         self.fstack,
@@ -724,55 +976,25 @@ impl Tokenizer<'_, '_, '_, '_, '_> {
                                     );
                                 }
                             }
-                        } else {
-                            // Regular ol' handling.
-                            let begin = self.cur_loc();
-                            self.bump_capture(false);
-
-                            match self.peek() {
-                                Some('\\' | '"' | '{' | '}') => self.bump_capture(true),
-                                Some('n') => {
-                                    append!('\n');
-                                    self.bump_capture(false);
-                                }
-                                Some('r') => {
-                                    append!('\r');
-                                    self.bump_capture(false);
-                                }
-                                Some('t') => {
-                                    append!('\t');
-                                    self.bump_capture(false);
-                                }
-                                Some(line_cont_start!()) => {
-                                    if let Err(kind) = self.discard_line_cont() {
-                                        let end = Location {
-                                            storage: begin.storage.clone(),
-                                            offset: self.cur_root_offset(),
-                                        };
-                                        self.reporter.borrow_mut().report_error(
-                                            self.fstack,
-                                            AsmError::new(begin, end, kind).into(),
-                                        );
-                                    }
-                                    todo!("Mark capture as disrupted");
-                                }
 
-                                // Do not bump the character, it will then be read normally on the next iteration.
-                                c => self.reporter.borrow_mut().report_error(
+                            // Do not bump the character, it will then be read normally on the next iteration.
+                            c => {
+                                let end = self.cur_loc();
+                                self.reporter.borrow_mut().report_error(
                                     self.fstack,
                                     AsmError::new(
                                         begin,
-                                        self.cur_loc(),
+                                        end,
                                         c.map_or(
                                             AsmErrorKind::IllegalEscapeEof,
                                             AsmErrorKind::IllegalEscape,
                                         ),
                                     )
                                     .into(),
-                                ),
+                                )
                             }
                         }
-                    });
+                    }
                 }
 
                 // Other characters get appended normally.
@@ -988,11 +1210,14 @@ impl Tokenizer<'_, '_, '_, '_, '_> {
                     c @ '0'..='9' => {
                         self.bump();
                         let int_part = self.read_number(c.to_digit(10).unwrap(), 10);
-                        Ok(if self.peek() == Some('.') {
-                            todo!();
+                        if self.peek() == Some('.') {
+                            // Skip the fractional part, so that it is not lexed on its own.
+                            self.bump();
+                            self.read_number(0, 10);
+                            Err(AsmErrorKind::Unsupported("Fixed-point numbers"))
                         } else {
-                            Token::Number(int_part)
-                        })
+                            Ok(Token::Number(int_part))
+                        }
                     }
                     '&' => {
                         self.bump();
@@ -1005,9 +1230,9 @@ impl Tokenizer<'_, '_, '_, '_, '_> {
                                 self.bump();
                                 Token::LogicAnd
                             }
-                            Some('0'..='7') => {
+                            Some(digit @ '0'..='7') => {
                                 self.bump();
-                                Token::Number(self.read_number(c.to_digit(8).unwrap(), 8))
+                                Token::Number(self.read_number(digit.to_digit(8).unwrap(), 8))
                             }
                             _ => Token::BitAnd,
                         })
@@ -1029,6 +1254,7 @@ impl Tokenizer<'_, '_, '_, '_, '_> {
                     '$' => {
                         self.bump();
                         if let Some(base_value) = self.peek().and_then(|ch| ch.to_digit(16)) {
+                            self.bump();
                             Ok(Token::Number(self.read_number(base_value, 16)))
                         } else {
                             Err(AsmErrorKind::NoHexDigits)
@@ -1060,28 +1286,38 @@ impl Tokenizer<'_, '_, '_, '_, '_> {
                                 true
                             } else {
                                 // An empty string.
-                                break 'string Ok(Token::String(SourceString::new()));
+                                self.expand_macro_args = true;
+                                self.enable_interpolation = true;
+                                break 'string Ok(Token::String(Box::default()));
                             }
                         } else {
                             false
                         };
 
                         self.start_capture();
-                        if let Err(()) = self.read_string_body(multiline) {
-                            todo!(); // Report an error
-                        }
+                        let mut raw_bytes = Vec::new();
+                        let res = self.read_string_body(multiline, Some(&mut raw_bytes));
                         self.expand_macro_args = true;
                         self.enable_interpolation = true;
 
                         let string = self.end_capture();
-                        self.bump(); // The closing quote.
-                        Ok(Token::String(string))
+                        match res {
+                            Ok(()) => {
+                                self.bump(); // The closing quote.
+                                Ok(Token::String(Box::new(StringValue {
+                                    text: string,
+                                    raw_bytes,
+                                })))
+                            }
+                            Err(()) => Err(AsmErrorKind::UnterminatedString),
+                        }
                     }
 
                     // Newline.
                     '\r' => {
                         self.bump();
-                        todo!();
+                        self.handle_crlf('\r');
+                        Ok(Token::Newline)
                     }
                     '\n' => {
                         self.bump();
@@ -1128,13 +1364,19 @@ impl Tokenizer<'_, '_, '_, '_, '_> {
                                     Token::Identifier(name)
                                 }
                             }
-                            Token::Def | Token::Redef | Token::Macro | Token::Purge => {
+                            Token::Def | Token::Redef | Token::Macro | Token::Purge | Token::For => {
                                 // After a `def` or a `redef`, EQUS must not be expanded.
                                 // This gets set back to `true` after reading the identifier,
                                 // or after recovering from a syntax error.
                                 self.lexer.borrow_mut().expand_equs = false;
                                 token
                             }
+                            Token::Opt => {
+                                // `OPT`'s arguments are not regular tokens, so they are read raw.
+                                // Raw mode automatically ends with the line.
+                                self.lexer.borrow_mut().mode = Mode::Raw;
+                                Token::Opt
+                            }
                             tok => tok,
                         })
                     }
@@ -1154,7 +1396,7 @@ impl Tokenizer<'_, '_, '_, '_, '_> {
                 Some(c) if is_whitespace(c) => {
                     self.bump();
                 }
-                Some('\\') => todo!(), // Check if it's a line cont.
+                // Line continuations are handled along with the other escapes below.
                 _ => break,
             }
         }
@@ -1180,10 +1422,9 @@ impl Tokenizer<'_, '_, '_, '_, '_> {
                     } else {
                         false
                     };
-                    let res = self.read_string_body(multiline);
-                    self.bump_capture(true); // The closing quote.
-
-                    if let Err(()) = res {
+                    if let Ok(()) = self.read_string_body(multiline, None) {
+                        self.bump_capture(true); // The closing quote.
+                    } else {
                         let end = self.cur_loc();
                         self.reporter.borrow_mut().report_error(
                             self.fstack,
@@ -1203,7 +1444,8 @@ impl Tokenizer<'_, '_, '_, '_, '_> {
                     let string = self.end_capture();
                     self.bump();
                     self.discard_comment();
-                    break (string, Some(';'));
+                    // The comment runs until the end of the line, which is then handled as above.
+                    break (string, self.peek());
                 }
 
                 // Block comment inside macro arg?
@@ -1213,7 +1455,17 @@ impl Tokenizer<'_, '_, '_, '_, '_> {
                         self.bump_capture(false);
                         // Pop off the `/`.
                         SourceString::trim_end(&mut self.capture.as_mut().unwrap().0, 1);
-                        todo!(); // Either duplicate `discard_block_comment` (meh), or temporarily disable the capture somehow.
+
+                        // Suspend the capture, so that the comment can be discarded as usual.
+                        let (capture, _) = self.capture.take().unwrap();
+                        let res = self.discard_block_comment();
+                        self.capture = Some((capture, true));
+                        if let Err(kind) = res {
+                            let end = self.cur_loc();
+                            self.reporter
+                                .borrow_mut()
+                                .report_error(self.fstack, AsmError::new(begin, end, kind).into());
+                        }
                     }
                 }
 
@@ -1226,7 +1478,13 @@ impl Tokenizer<'_, '_, '_, '_, '_> {
                 Some('\\') => {
                     self.bump_capture(false);
                     match self.peek() {
-                        None => todo!(), // Report unterminated char escape in macro arg
+                        None => {
+                            let end = self.cur_loc();
+                            self.reporter.borrow_mut().report_error(
+                                self.fstack,
+                                AsmError::new(begin, end, AsmErrorKind::IllegalEscapeEof).into(),
+                            );
+                        }
                         Some(line_cont_start!()) => {
                             if let Err(kind) = self.discard_line_cont() {
                                 return Some((Err(kind), start_offset));
@@ -1234,12 +1492,17 @@ impl Tokenizer<'_, '_, '_, '_, '_> {
                         }
                         Some(c) => {
                             self.bump_capture(false);
-                            let to_push = if let Some(escape) = escape_char(c, true) {
-                                escape
+                            if let Some(escape) = escape_char(c, true) {
+                                SourceString::make_owned(&mut self.capture.as_mut().unwrap().0)
+                                    .push(escape);
                             } else {
-                                todo!(); // Report invalid char escape in macro
-                            };
-                            SourceString::push(&mut self.capture.as_mut().unwrap().0, to_push);
+                                let end = self.cur_loc();
+                                self.reporter.borrow_mut().report_error(
+                                    self.fstack,
+                                    AsmError::new(begin, end, AsmErrorKind::IllegalEscape(c))
+                                        .into(),
+                                );
+                            }
                         }
                     }
                 }
@@ -1262,7 +1525,7 @@ impl Tokenizer<'_, '_, '_, '_, '_> {
         // Commas permit empty arguments (i.e. two commas separated by whitespace only).
         if trimmed_len != 0 || last_char == Some(',') {
             SourceString::truncate(&mut string, trimmed_len);
-            Some(Token::String(string))
+            Some(Token::String(Box::new(string.into())))
         } else {
             // This is a token that ends the line, and "raw mode" only lasts until the end of its line.
             // (Doing this saves having to inject "lookahead hack" tokens.)
@@ -1298,26 +1561,31 @@ impl Tokenizer<'_, '_, '_, '_, '_> {
         self.enable_interpolation = false;
 
         self.start_capture();
-        let res = loop {
+        let terminated = loop {
             // We are at the beginning of a line, so attempt to match an `ENDM` token.
             match self.peek() {
-                None => break Err(AsmErrorKind::UnterminatedMacro), // TODO: this would be wrong for REPTs!
+                // The parser knows what is being captured, so it reports this itself.
+                None => break false,
                 Some(c) => {
                     self.bump_capture(true);
                     if can_start_ident(c) && self.read_specific_keyword(c, end_keyword) {
-                        break Ok(());
+                        break true;
                     }
                 }
             }
         };
         let mut body = self.end_capture();
         let read_len = body.len();
-        SourceString::trim_end(&mut body, end_tok_len);
+        if terminated {
+            SourceString::trim_end(&mut body, end_tok_len);
+        }
 
         self.expand_macro_args = true;
         self.enable_interpolation = true;
-        self.lexer.borrow_mut().mode = Mode::Normal; // Automatically revert back to normal mode.
-        Some((res.map(|()| Token::String(body)), read_len))
+        let mut lexer = self.lexer.borrow_mut();
+        lexer.mode = Mode::Normal; // Automatically revert back to normal mode.
+        lexer.unterminated_capture = !terminated;
+        Some((Ok(Token::String(Box::new(body.into()))), read_len))
     }
 }
 
@@ -1346,7 +1614,13 @@ impl<'fstack> Iterator for Tokenizer<'fstack, '_, '_, '_, '_> {
                     let loc = Self::location(Some(cur_node.clone()), self.cur_root_offset());
                     // FIXME: if you have a `INCLUDE` at EOL without a newline, this will pop off its parent node *before* excuting the `INCLUDE`!!
                     //        This can be fixed by controlling that the INCLUDE is executed before the newline, but that would require either a "lexer hack" injection (likely right after parsing the `INCLUDE`), or a hand-written parser.
-                    self.fstack.handle_end_of_node(&mut self.lexer.borrow_mut());
+                    let for_var = self.fstack.handle_end_of_node(
+                        &mut self.lexer.borrow_mut(),
+                        &mut self.macro_args.borrow_mut(),
+                    );
+                    if let Some((name, value)) = for_var {
+                        self.symbols.borrow_mut().set_for_var(&name, value);
+                    }
                     return Some(Ok((loc.clone(), Token::Newline, loc)));
                 }
                 Some(token) => token,
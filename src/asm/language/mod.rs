@@ -1,25 +1,29 @@
 use std::{
     fmt::{Display, Write},
+    ops::RangeInclusive,
     write,
 };
 
-use codespan_reporting::diagnostic::{Diagnostic, Label};
+use codespan_reporting::diagnostic::Diagnostic;
 use lalrpop_util::lalrpop_mod;
 use parse_display::Display;
-use rgbds::{
+use crate::{
     rpn::EvalError,
     section::{Kind as SectionKind, Modifier},
 };
 
 mod lexer;
-pub use lexer::{Lexer, Location, Tokenizer};
+pub use lexer::{Lexer, Location, Span, StringValue, Tokenizer};
 lalrpop_mod!(parser, "/asm/language/parser.rs");
 pub use parser::TranslationUnitParser as Parser;
 mod tokens;
-use tokens::Token;
+pub use tokens::Token;
 use warnings_gen::Warnings;
 
-use crate::{fstack::DiagInfo, input::SourceString, instructions::BadInstructionKind};
+use crate::asm::{
+    error::DiagnosticBuilder, fstack::DiagInfo, input::SourceString,
+    instructions::BadInstructionKind, options::OptError,
+};
 
 pub type ParseError<'fstack> =
     lalrpop_util::ParseError<Location<'fstack>, Token, AsmError<'fstack>>;
@@ -51,9 +55,24 @@ pub enum WarningKind {
     /// Empty second argument in `STRRPL`
     #[warning(default = false)]
     EmptyStrrpl,
+    /// Omitted `a` destination operand
+    #[warning(default = false)]
+    ImplicitA,
+    /// Invalid `OPT` arguments
+    #[warning(default = true)]
+    InvalidOpt(OptError),
+    /// `jr` to a label in another section
+    #[warning(default = true)]
+    JrCrossSection(SourceString),
     /// Constants too large
     #[warning(default = false)]
     LargeConstant,
+    /// Large `ds` in ROM
+    #[warning(default = false)]
+    LargeDs(usize),
+    /// `ldi` and `ldd` instead of `ld [hl+]` and `ld [hl-]`
+    #[warning(default = false)]
+    LegacyLd(bool),
     /// Shift past available arguments in macro
     #[warning(default = false)]
     MacroShift,
@@ -75,10 +94,21 @@ pub enum WarningKind {
     ShiftAmount,
     /// Implicit truncation loses some bits
     #[warning(default = 1, max = 2)]
-    Truncation { level: u8, width: u8 },
+    Truncation {
+        level: u8,
+        width: u8,
+        value: i32,
+        range: RangeInclusive<i32>,
+    },
     /// Character without charmap entry
     #[warning(default = 1, max = 2)]
     UnmappedChar { level: u8, ch: char },
+    /// Data not preceded by any label
+    #[warning(default = false)]
+    UnlabeledData,
+    /// Labels never referenced nor exported
+    #[warning(default = false)]
+    Unused(SourceString),
     /// User warnings
     #[warning(default = true)]
     User(SourceString),
@@ -90,8 +120,8 @@ pub enum WarningKind {
         EmptyDataDirective,
         EmptyStrrpl,
         LargeConstant,
-        LongStr,
-        NestedComment,
+        // TODO: `LongStr`, once strings have a length limit
+        NestedBlockComment,
         Obsolete,
         NumericString1,
         UnmappedChar1
@@ -108,21 +138,40 @@ pub enum WarningKind {
     Extra,
     #[warning(meta(Everything))]
     Everything,
+    #[warning(meta(
+        ImplicitA,
+        LargeDs,
+        // TODO: `SetInsteadOfEqu`, once `=`/`SET` variables are implemented
+        UnlabeledData
+    ))]
+    Pedantic,
 }
 
 impl Display for WarningKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Assert => todo!(),
-            Self::BackwardsFor => todo!(),
-            Self::BuiltinArg => todo!(),
-            Self::CharmapRedef => todo!(),
-            Self::Div => todo!(),
-            Self::EmptyDataDirective => todo!(),
+            Self::Assert => write!(f, "Assertion failed"),
+            Self::BackwardsFor => write!(f, "`FOR` goes backwards, so it does not loop"),
+            Self::BuiltinArg => write!(f, "Invalid argument to a built-in function"),
+            Self::CharmapRedef => write!(f, "Overriding a charmap mapping"),
+            Self::Div => write!(f, "Division of the minimum value by -1"),
+            Self::EmptyDataDirective => write!(f, "Data directive without data in ROM"),
             Self::EmptyMacroArg => write!(f, "Empty macro argument"),
-            Self::EmptyStrrpl => todo!(),
-            Self::LargeConstant => todo!(),
-            Self::MacroShift => todo!(),
+            Self::EmptyStrrpl => write!(f, "`STRRPL` cannot replace an empty string"),
+            Self::ImplicitA => write!(f, "Destination operand `a` is implicit"),
+            Self::InvalidOpt(err) => err.fmt(f),
+            Self::JrCrossSection(label) => {
+                write!(f, "`jr` target \"{label}\" is in a different section")
+            }
+            Self::LargeConstant => write!(f, "Integer constant is too large"),
+            Self::LargeDs(len) => write!(f, "Reserving {len} bytes of ROM with `ds`"),
+            Self::LegacyLd(inc) => write!(
+                f,
+                "`{}` is a legacy spelling of `ld [hl{}]`",
+                if *inc { "ldi" } else { "ldd" },
+                if *inc { '+' } else { '-' },
+            ),
+            Self::MacroShift => write!(f, "Cannot shift macro arguments past their end"),
             Self::NestedBlockComment => write!(f, "\"/*\" within block comment"),
             Self::NumericString { level, len } => match level {
                 1 => write!(
@@ -135,17 +184,33 @@ impl Display for WarningKind {
                 _ => unreachable!(),
             },
             Self::Obsolete(kind) => kind.fmt(f),
-            Self::Shift => todo!(),
-            Self::ShiftAmount => todo!(),
-            Self::Truncation { level: _, width } => write!(f, "This expression does not fit in {width} bits"),
+            Self::Shift => write!(f, "Shifting a negative value"),
+            Self::ShiftAmount => write!(f, "Shift amount is negative, or 32 or more"),
+            Self::Truncation { level: _, width, value, range } => {
+                let (excess, direction) = if value > range.end() {
+                    (i64::from(*value) - i64::from(*range.end()), "large")
+                } else {
+                    (i64::from(*range.start()) - i64::from(*value), "small")
+                };
+                write!(
+                    f,
+                    "Value {}${:X} does not fit in {width} bits (from {} to {}, {excess} too {direction})",
+                    if *value < 0 { "-" } else { "" },
+                    value.unsigned_abs(),
+                    range.start(),
+                    range.end(),
+                )
+            }
             Self::UnmappedChar { level, ch } => match level {
                 1 => write!(f, "Character '{}' is not in charmap", ch.escape_default()),
-                2 => write!(f, "Character '{}' is not in charmap {}", ch.escape_default(), todo!()),
+                2 => write!(f, "Character '{}' is not in the main charmap", ch.escape_default()),
                 _ => unreachable!(),
             },
+            Self::UnlabeledData => write!(f, "Data is not preceded by any label"),
+            Self::Unused(name) => write!(f, "Label \"{name}\" is never referenced"),
             Self::User(msg) => write!(f, "{msg}"),
 
-            Self::All | Self::Extra | Self::Everything => unreachable!(),
+            Self::All | Self::Extra | Self::Everything | Self::Pedantic => unreachable!(),
         }
     }
 }
@@ -156,9 +221,11 @@ pub enum ObsoleteKind {
     LdCA,
     #[display("`ld a, [c]` is deprecated and will be removed in an upcoming version")]
     LdAC,
+    #[display("`{0}` is deprecated and will be removed in an upcoming version")]
+    LegacyPrint(&'static str),
 }
 
-#[derive(Debug, Display)]
+#[derive(Debug, Display, Clone)]
 pub enum SymEvalErrKind {
     #[display("Symbol \"{0}\" does not exist")]
     NoSuchSymbol(SourceString),
@@ -170,6 +237,12 @@ pub enum SymEvalErrKind {
     NargOutsideMacro,
     #[display("\"@\" is only defined within a section")]
     PcOutsideSection,
+    #[display("\"SECTSTART\" is only defined within a section")]
+    SectStartOutsideSection,
+    #[display("Section \"{0}\" is floating, so its start address is not known yet")]
+    FloatingSection(String),
+    #[display("Not supported yet: {0}")]
+    Unsupported(&'static str),
 }
 
 #[derive(Debug, Display)]
@@ -197,26 +270,46 @@ pub enum AsmErrorKind {
     ExtraToken(Token),
     #[display("Syntax error: unterminated block comment")]
     UnterminatedBlockComment,
-    #[display("Syntax error: unterminated macro")]
+    #[display("Unterminated macro definition")]
     UnterminatedMacro,
+    #[display("Unterminated loop")]
+    UnterminatedLoop,
     #[display("Syntax error: unterminated string literal")]
     UnterminatedString,
     #[display("Syntax error: no hexadecimal digits found after '$'")]
     NoHexDigits,
     #[display("Syntax error: no graphics \"digits\" found after '`'")]
     NoGfxChars([char; 4]),
+    #[display("Syntax error: digit separators cannot be doubled")]
+    DoubleSeparator,
+    #[display("Syntax error: numbers cannot end with a digit separator")]
+    TrailingSeparator,
     #[display("Cannot escape '{0}'")]
     IllegalEscape(char),
     #[display("Character being escaped is missing")]
     IllegalEscapeEof,
+    #[display("`\\x` must be followed by two hexadecimal digits")]
+    BadHexEscape,
+    #[display("Raw bytes from `\\x` escapes cannot be used outside of data")]
+    RawBytesInText,
+    #[display("Syntax error: line continuation followed by '{0}' instead of a newline")]
+    BadLineCont(char),
+    #[display("Syntax error: line continuation at end of file")]
+    LineContEof,
     #[display("Invalid instruction: {0}")]
     BadInstruction(BadInstructionKind),
+    #[display("Not supported yet: {0}")]
+    Unsupported(&'static str),
 
     // Semantic errors.
     #[display("{0} is already defined")]
     SymAlreadyDefined(SourceString, DiagInfo),
+    #[display("Constant \"{0}\" is defined in terms of itself ({1})")]
+    EquCycle(SourceString, String),
     #[display("Only labels can be local")]
     IllegalLocal,
+    #[display("Label \"{0}\" defined outside of any section")]
+    LabelOutsideSection(SourceString),
     #[display("Symbol \"{0}\" does not exist")]
     NoSuchSymbol(SourceString),
     // TODO: report the actual kind as "help"
@@ -224,6 +317,14 @@ pub enum AsmErrorKind {
     SymNotEqus(SourceString),
     #[display("Symbol \"{0}\" is not a macro")]
     SymNotMacro(SourceString),
+    #[display("`ENDM` found outside of a macro definition")]
+    EndmWithoutMacro,
+    #[display("Macro arguments cannot be shifted outside of a macro")]
+    ShiftOutsideMacro,
+    #[display("Cannot shift macro arguments past their end")]
+    ShiftPastEnd,
+    #[display("Cannot shift macro arguments past their beginning")]
+    ShiftPastBeginning,
     #[display("Built-in symbol \"{0}\" cannot be purged")]
     PurgingBuiltin(SourceString),
     #[display("Symbol \"{0}\" is referenced and thus cannot be purged")]
@@ -231,6 +332,28 @@ pub enum AsmErrorKind {
     #[display("{0}")]
     EvalError(EvalError<SymEvalErrKind>),
 
+    // File inclusion errors.
+    #[display("Unable to find included file \"{0}\"")]
+    IncludeNotFound(SourceString),
+    #[display("Unable to read included file \"{0}\": {1}")]
+    IncludeReadError(SourceString, std::io::Error),
+    #[display("Unable to read input file \"{0}\": {1}")]
+    InputReadError(SourceString, std::io::Error),
+    #[display("Unable to write section JSON to \"{0}\": {1}")]
+    SectionJsonWriteError(SourceString, std::io::Error),
+    #[display("Recursion limit ({0}) exceeded")]
+    RecursionTooDeep(usize),
+    #[display("This loop would run {0} times, more than the limit of {1}")]
+    TooManyIterations(i64, u32),
+    #[display("`FOR` cannot have a step of 0")]
+    ForZeroStep,
+    #[display("`INCBIN` start and length cannot be negative ({0})")]
+    NegativeIncbinArg(i32),
+    #[display("`INCBIN` starts at byte {0}, past the end of the {1}-byte file")]
+    IncbinStartPastEnd(usize, usize),
+    #[display("`INCBIN` of {1} bytes from byte {0} goes past the end of the {2}-byte file")]
+    IncbinRangePastEnd(usize, usize, usize),
+
     // Section definition errors.
     #[display("{0} is already defined")]
     SectAlreadyDefined(SourceString, DiagInfo),
@@ -242,10 +365,54 @@ pub enum AsmErrorKind {
     //       mainly because this would require tracking source info with much more granularity.
     #[display("Conflicting banks specified for {0} (previously {1}, now {2})")]
     DifferentBank(SourceString, u32, u32),
+    #[display("Conflicting fill bytes specified for {0} (previously ${1:02x}, now ${2:02x})")]
+    DifferentFill(SourceString, u8, u8),
+    #[display("Conflicting addresses specified for {0} (previously ${1:04x}, now ${2:04x})")]
+    DifferentAddr(SourceString, u16, u16),
+    #[display("Conflicting alignments specified for {0}")]
+    DifferentAlign(SourceString),
     #[display("Cannot declare a {0} section as union")]
     RomUnion(SectionKind),
 
+    // `LOAD` block errors.
+    #[display("`LOAD` block found outside of any section")]
+    LoadOutsideSection,
+    #[display("`LOAD` blocks cannot be nested")]
+    NestedLoad,
+    #[display("`LOAD` blocks cannot create a {0} section")]
+    RomLoad(SectionKind),
+    #[display("`ENDL` found outside of a `LOAD` block")]
+    EndlWithoutLoad,
+    #[display("Unterminated `LOAD` block")]
+    UnterminatedLoad,
+    #[display("`ENDL` found before the `ENDU` of a `UNION` started inside the `LOAD` block")]
+    EndlInUnion,
+
+    // `UNION` block errors.
+    #[display("`UNION` found outside of any section")]
+    UnionOutsideSection,
+    #[display("`UNION`s cannot be used in {0} sections")]
+    UnionInRom(SectionKind),
+    #[display("`{0}` found outside of a `UNION`")]
+    NoActiveUnion(&'static str),
+    #[display("`{0}` found before the `ENDL` of a `LOAD` block started inside the `UNION`")]
+    UnionAcrossLoad(&'static str),
+    #[display("Cannot change the section within a `UNION`")]
+    SectionInUnion,
+    #[display("Unterminated `UNION`")]
+    UnterminatedUnion,
+
+    // Option stack errors.
+    #[display("`POPO` found without a matching `PUSHO`")]
+    PopoWithoutPusho,
+
     // Section specification errors.
+    #[display("Unknown section attribute \"{0}\"")]
+    UnknownSectAttr(SourceString),
+    #[display("Section attribute \"{0}\" does not take an argument")]
+    SectAttrNoArg(SourceString),
+    #[display("Section attribute \"{0}\" requires an argument, e.g. \"{0}[...]\"")]
+    SectAttrNeedsArg(SourceString),
     #[display("An address must be in 16-bit range, not ${0:04x}")]
     AddrOutOfRange(i32),
     #[display("Alignment must be between 0 and 16 (inclusive), not {0}")]
@@ -254,6 +421,8 @@ pub enum AsmErrorKind {
         "Alignment offset (${0:02x}) must not be greater than the alignment boundary (${1:02x})"
     )]
     AlignOfsOutOfRange(i32, i32),
+    #[display("Fill byte must be between 0 and 255 (inclusive), not {0}")]
+    FillOutOfRange(i32),
     #[display("BANK[...] is not allowed for {0} sections")]
     Unbanked(SectionKind),
     #[display("Bank number (${0:04x}) must be between ${1:02x} and ${2:02x}")]
@@ -265,6 +434,12 @@ pub enum AsmErrorKind {
     #[display("{0}-bit alignment is impossible for {1} sections")]
     OverAligned(u8, SectionKind),
 
+    // Instruction operand errors.
+    #[display("Bit number must be between 0 and 7, not {0}")]
+    BitOutOfRange(i32),
+    #[display("Only `$ff00` can be added to `c`, not ${0:04x}")]
+    NotHramBase(i32),
+
     // Data output errors.
     #[display("Data found outside of any section")]
     DataOutsideSection, // TODO: show the `PUSHS` that reset the section scope, or print help text warning that no section was ever started (suggest starting one either way)
@@ -272,22 +447,38 @@ pub enum AsmErrorKind {
     InstrOutsideSection,
     #[display("Only ROM0 and ROMX sections can contain data, not {0}")]
     NotCodeSection(SectionKind),
+    #[display("Cannot reserve a negative amount of bytes ({0})")]
+    NegativeDsLen(i32),
+    #[display("A {0}-byte string does not fit in `ds {1}`")]
+    DsStringTooLong(usize, usize),
+    #[display(
+        "Byte {0} of the {1} output here overflows the section, which can only hold {2} bytes"
+    )]
+    SectionOverflow(usize, usize, usize),
+    #[display("Outputting {0} more bytes would make the section's size overflow")]
+    SectionSizeOverflow(usize),
 }
 
 impl WarningKind {
     pub fn notes(&self) -> Vec<String> {
         // TODO: ew, `String`s here instead of `Display`?
         match self {
+            Self::JrCrossSection(..) => {
+                vec!["Whether it is within range can only be checked by the linker".to_string()]
+            }
             Self::Obsolete(kind) => kind.notes(),
+            Self::Unused(..) => {
+                vec!["If it is meant to be used by other files, export it with `::`".to_string()]
+            }
 
-            Self::All | Self::Extra | Self::Everything => unreachable!(),
+            Self::All | Self::Extra | Self::Everything | Self::Pedantic => unreachable!(),
             _ => vec![],
         }
     }
 
-    pub fn labels(&self, labels: &mut Vec<Label<usize>>) {
+    pub fn labels(&self, builder: DiagnosticBuilder) -> DiagnosticBuilder {
         match self {
-            _ => {}
+            _ => builder,
         }
     }
 }
@@ -303,6 +494,7 @@ impl ObsoleteKind {
         match self {
             Self::LdCA => vec![format!("Please use `ldh [c], a` instead")],
             Self::LdAC => vec![format!("Please use `ldh a, [c]` instead")],
+            Self::LegacyPrint(_) => vec![format!("Please use `PRINT` instead")],
         }
     }
 }
@@ -312,6 +504,18 @@ impl AsmErrorKind {
         // TODO: ew, `String`s here instead of `Display`?
         match self {
             Self::DiffMark(_) => vec!["Is it a leftover diff mark?".to_string()],
+            Self::UnterminatedMacro => {
+                vec!["The file ended before the matching `ENDM`".to_string()]
+            }
+            Self::UnterminatedLoop => {
+                vec!["The file ended before the matching `ENDR`".to_string()]
+            }
+            Self::UnterminatedLoad => {
+                vec!["The input ended before the matching `ENDL`".to_string()]
+            }
+            Self::UnterminatedUnion => {
+                vec!["The input ended before the matching `ENDU`".to_string()]
+            }
             Self::UnrecognizedEOF(expected) => {
                 vec![format!("Expected {}", ExpectedTokens(expected))]
             }
@@ -329,6 +533,12 @@ impl AsmErrorKind {
             Self::Unbanked(..) => vec![
                 "BANK[...] is only allowed for ROMX, VRAM, SRAM, and WRAMX sections".to_string(),
             ],
+            Self::RecursionTooDeep(..) => {
+                vec!["The limit can be changed with `-r` or `OPT r`".to_string()]
+            }
+            Self::TooManyIterations(..) => {
+                vec!["The limit can be changed with `--max-loop-iterations`".to_string()]
+            }
             Self::AlignMismatch(addr, align, _) => vec![format!(
                 "ALIGN[{align}, {}] would work",
                 addr & ((1 << align) - 1)
@@ -338,42 +548,16 @@ impl AsmErrorKind {
         }
     }
 
-    pub fn labels(&self, labels: &mut Vec<Label<usize>>) {
+    pub fn labels(&self, builder: DiagnosticBuilder) -> DiagnosticBuilder {
         match self {
-            Self::SectAlreadyDefined(_, prev_def_info) => {
-                if let Some((file_id, range)) = prev_def_info {
-                    labels.push(
-                        Label::secondary(*file_id, range.clone())
-                            .with_message("Previously defined here"),
-                    );
-                }
-            }
-            Self::DifferentSectMod(_, _, prev_def_info) => {
-                if let Some((file_id, range)) = prev_def_info {
-                    labels.push(
-                        Label::secondary(*file_id, range.clone())
-                            .with_message("Previously defined here"),
-                    );
-                }
-            }
-            Self::DifferentSectKind(_, _, prev_def_info) => {
-                if let Some((file_id, range)) = prev_def_info {
-                    labels.push(
-                        Label::secondary(*file_id, range.clone())
-                            .with_message("Previously defined here"),
-                    );
-                }
-            }
-            Self::SymAlreadyDefined(_, prev_def_info) => {
-                if let Some((file_id, range)) = prev_def_info {
-                    labels.push(
-                        Label::secondary(*file_id, range.clone())
-                            .with_message("Previously defined here"),
-                    );
-                }
+            Self::SymAlreadyDefined(_, prev_def_info)
+            | Self::SectAlreadyDefined(_, prev_def_info)
+            | Self::DifferentSectMod(_, _, prev_def_info)
+            | Self::DifferentSectKind(_, _, prev_def_info) => {
+                builder.previous_definition(prev_def_info)
             }
 
-            _ => {}
+            _ => builder,
         }
     }
 
@@ -1,7 +1,9 @@
 use keyword_trie_gen::keyword_trie;
 use parse_display::Display;
 
-use crate::input::SourceString;
+use crate::asm::input::SourceString;
+
+use super::StringValue;
 
 pub fn can_start_ident(ch: char) -> bool {
     matches!(ch, 'A'..='Z' | 'a'..='z' | '_')
@@ -18,8 +20,9 @@ pub fn can_start_ident(ch: char) -> bool {
 pub enum Token {
     #[display("number")]
     Number(u32),
+    /// Boxed to keep tokens, and thus parse errors, small.
     #[display("string")]
-    String(SourceString),
+    String(Box<StringValue>),
 
     // Punctuation.
     #[display("\".\"")]
@@ -93,6 +96,7 @@ pub enum Token {
     Align,
     Sizeof,
     Startof,
+    Sectstart,
     Opcode,
 
     // Built-in fixed-point functions.
@@ -184,6 +188,9 @@ pub enum Token {
     Purge,
     Print,
     Println,
+    Printt,
+    Printv,
+    Printi,
     If,
     Elif,
     Else,
@@ -1,6 +1,6 @@
 use std::rc::Rc;
 
-use crate::input::SourceString;
+use crate::asm::{input::SourceString, language::AsmErrorKind};
 
 #[derive(Debug, Clone)]
 pub struct MacroArgs {
@@ -13,20 +13,37 @@ impl MacroArgs {
         Self { args, shift: 0 }
     }
 
+    /// How many arguments have not been shifted out yet.
     pub fn nb_args(&self) -> usize {
-        self.args.len()
+        self.args.len() - self.shift
     }
 
     pub fn get(&self, idx: usize) -> Option<&Rc<SourceString>> {
         self.args.get(idx + self.shift - 1)
     }
 
+    /// Shifts the arguments by `amount`, which may be negative to "unshift" them.
+    pub fn shift(&mut self, amount: i32) -> Result<(), AsmErrorKind> {
+        let shift = isize::try_from(amount)
+            .ok()
+            .and_then(|amount| self.shift.checked_add_signed(amount));
+        match shift {
+            None => Err(AsmErrorKind::ShiftPastBeginning),
+            Some(shift) if shift > self.args.len() => Err(AsmErrorKind::ShiftPastEnd),
+            Some(shift) => {
+                self.shift = shift;
+                Ok(())
+            }
+        }
+    }
+
+    /// The arguments that have not been shifted out yet, joined with commas.
     pub fn make_concat(&self) -> Rc<SourceString> {
-        let mut args = self.args.iter();
+        let mut args = self.args[self.shift..].iter();
         match args.next() {
             None => Rc::new(SourceString::new()),
             Some(first_arg) => {
-                if self.args.len() == 1 {
+                if self.nb_args() == 1 {
                     Rc::clone(first_arg)
                 } else {
                     let mut string = SourceString::clone(first_arg);
@@ -41,3 +58,50 @@ impl MacroArgs {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::test_utils::{assemble_with, error_lines};
+
+    #[test]
+    fn forward_all_args() {
+        let (diagnostics, data) = assemble_with(
+            "shift.asm",
+            r#"MACRO inner
+    db \#
+ENDM
+MACRO outer
+    inner \#
+    SHIFT 2
+    inner \#
+    SHIFT -1
+    inner \#
+    SHIFT 3
+ENDM
+MACRO wrap
+    inner \#
+    db 5 \#
+ENDM
+SECTION "a", ROM0
+    outer 1, 2, 3
+    wrap
+    SHIFT
+    db \#
+"#,
+            |_, _| {},
+            |sections| sections.active_section().unwrap().data().to_vec(),
+        );
+        // With no arguments left, `\#` expands to nothing; a `db` left without any arguments
+        // reserves a single byte.
+        assert_eq!(data, [1, 2, 3, 3, 2, 3, 0, 5, 0], "{diagnostics}");
+        assert_eq!(
+            error_lines(&diagnostics),
+            [
+                "error: Cannot shift macro arguments past their end",
+                "error: Macro arguments cannot be shifted outside of a macro",
+                "error: Syntax error: macro argument is being used outside of a macro",
+            ],
+            "{diagnostics}"
+        );
+    }
+}
@@ -1,50 +1,105 @@
 use std::{cell::RefCell, fs::File, rc::Rc};
 
-mod error;
-use error::Reporter;
-mod expr;
-mod fstack;
-use fstack::Fstack;
-mod input;
-use input::{SourceString, Storage};
-mod instructions;
-mod language;
-use language::{Lexer, Parser, Tokenizer};
-mod macro_args;
-mod sections;
-use sections::Sections;
-mod symbols;
-use symbols::Symbols;
+use clap::Parser as _;
+
+use rgbds::asm::{
+    assemble::{self, timed},
+    cli::Cli,
+    error::{Reporter, WarningState},
+    fstack::Fstack,
+    input::{SourceString, Storage},
+    language::{AsmErrorKind, WarningId},
+    options::Options,
+    sections::Sections,
+    symbols::Symbols,
+};
 
 fn main() {
-    // TODO: arg parsing
-
-    // TODO: colour choice
-    let mut reporter = RefCell::new(Reporter::new(
-        codespan_reporting::term::termcolor::ColorChoice::Always,
-    ));
-
-    let root_path = "/tmp/test.asm"; // TODO
-    let root_file = File::open(root_path).expect("Failed to open root file"); // TODO: also support stdin/stdout
-    let root_file = Rc::new(
-        Storage::from_file(root_path.to_string().into(), &root_file)
-            .expect("Failed to read root file"),
-    );
-    let fstack = Fstack::new(root_file);
+    let cli = Cli::parse();
+
+    let mut reporter = RefCell::new(Reporter::new(cli.color.for_stderr()));
+    reporter.get_mut().set_format(cli.error_format);
+    if let Some(tab_width) = cli.tab_width {
+        reporter.get_mut().set_tab_width(tab_width);
+    }
+    if cli.pedantic {
+        for &id in WarningId::PEDANTIC {
+            reporter
+                .get_mut()
+                .set_warning_state(id, WarningState::Enabled);
+        }
+    }
+    if cli.dedup_diagnostics {
+        reporter.get_mut().set_dedup(true);
+    }
+    if let Some(max_errors) = cli.max_errors {
+        reporter.get_mut().set_max_errors(max_errors);
+    }
+    for flag in &cli.warnings {
+        reporter.get_mut().apply_warning_flag(flag);
+    }
+
+    let mut options = Options::default();
+    if let Some(pad_byte) = cli.pad_byte {
+        options.pad_byte = pad_byte;
+    }
+    if let Some(max_recursion_depth) = cli.max_recursion_depth {
+        options.max_recursion_depth = max_recursion_depth;
+    }
+    if let Some(max_loop_iterations) = cli.max_loop_iterations {
+        options.max_loop_iterations = max_loop_iterations;
+    }
+    if let Some(print_radix) = cli.print_radix {
+        options.print_radix = print_radix;
+    }
+    let options = RefCell::new(options);
+
+    // Timings are printed as they come, so that they are interleaved with diagnostics sensibly.
+    let log = || cli.verbose.then(std::io::stderr);
+
+    let input_name: SourceString = cli.input.display().to_string().into();
+    let root_file = timed(log(), "Reading input", || {
+        // TODO: also support stdin/stdout
+        File::open(&cli.input)
+            .and_then(|file| Storage::from_file(input_name.clone(), &file, cli.input_encoding))
+    });
+    // If the input cannot be read, an empty stand-in lets the error be reported like any other.
+    let (root_file, read_error) = match root_file {
+        Ok(root_file) => (root_file, None),
+        Err(err) => (
+            Storage::from_readable(input_name.clone(), &b""[..])
+                .expect("Reading from a slice cannot fail"),
+            Some(err),
+        ),
+    };
+    let mut fstack = Fstack::new(Rc::new(root_file), cli.include_paths);
+    fstack.set_encoding(cli.input_encoding);
+    if let Some(err) = read_error {
+        let kind = AsmErrorKind::InputReadError(input_name, err);
+        reporter.get_mut().report_unlocated_error(&fstack, kind);
+    }
     let sections = RefCell::new(Sections::new());
     let symbols = RefCell::new(Symbols::new());
-    let lexer = RefCell::new(Lexer::new());
-    let macro_args = RefCell::new(Vec::new());
-
-    if let Err(error) = Parser::new().parse(
-        &fstack,
-        &lexer,
-        &macro_args,
-        &sections,
-        &symbols,
-        &reporter,
-        Tokenizer::new(&fstack, &lexer, &macro_args, &reporter, &symbols),
-    ) {
-        reporter.get_mut().report_fatal_error(&fstack, error);
-    };
+    let stdout = RefCell::new(std::io::stdout());
+    timed(log(), "Assembling", || {
+        assemble::run(&fstack, &reporter, &options, &sections, &symbols, &stdout)
+    });
+
+    let reporter = reporter.get_mut();
+    // The section table is only meaningful if assembly succeeded.
+    if let (0, Some(path)) = (reporter.nb_errors(), &cli.section_json) {
+        if let Err(err) = std::fs::write(path, sections.borrow().to_json()) {
+            let kind = AsmErrorKind::SectionJsonWriteError(path.display().to_string().into(), err);
+            reporter.report_unlocated_error(&fstack, kind);
+        }
+    }
+
+    let nb_errors = reporter.nb_errors();
+    if nb_errors != 0 {
+        eprintln!(
+            "Assembly aborted ({nb_errors} error{})!",
+            if nb_errors == 1 { "" } else { "s" }
+        );
+        std::process::exit(1);
+    }
 }
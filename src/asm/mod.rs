@@ -0,0 +1,21 @@
+//! The assembler, usable both by the `rgbasm` binary and as a library.
+
+pub mod assemble;
+pub mod cli;
+pub mod error;
+mod expr;
+pub mod fstack;
+use fstack::Fstack;
+pub mod input;
+use input::{SourceString, Storage};
+mod instructions;
+pub mod language;
+mod macro_args;
+pub mod options;
+pub mod sections;
+pub mod symbols;
+#[cfg(test)]
+mod test_utils;
+
+pub use assemble::{assemble_str, tokenize, try_assemble, AssembleResult};
+pub use language::Token;
@@ -0,0 +1,229 @@
+use std::{fmt::Display, str::FromStr};
+
+use parse_display::Display;
+
+use crate::asm::{input::SourceString, language::AsmErrorKind};
+
+/// Settings that can be changed from the command line, and then at runtime with `OPT`.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The byte used to fill space reserved in ROM sections.
+    pub pad_byte: u8,
+    /// How deeply files, macros, and loops may be nested.
+    pub max_recursion_depth: usize,
+    /// How many times a single `REPT` or `FOR` may iterate.
+    pub max_loop_iterations: u32,
+    /// How `PRINT` formats numbers that are not given a format.
+    pub print_radix: PrintRadix,
+    /// Options saved by `PUSHO`, most recent last.
+    stack: Vec<Options>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            pad_byte: 0x00,
+            max_recursion_depth: 64,
+            max_loop_iterations: 65536,
+            print_radix: PrintRadix::Hex,
+            stack: Vec::new(),
+        }
+    }
+}
+
+impl Options {
+    /// Applies a single `OPT` argument, which is a flag letter immediately followed by its value.
+    pub fn apply(&mut self, arg: &SourceString) -> Result<(), OptError> {
+        let mut chars = arg.chars();
+        let Some(flag) = chars.next() else {
+            return Err(OptError::Empty);
+        };
+        let value = chars.as_str();
+        let bad_value = || OptError::BadValue(flag, arg.clone());
+
+        match flag {
+            'p' => self.pad_byte = parse_hex_byte(value).ok_or_else(bad_value)?,
+            'r' => self.max_recursion_depth = parse_number(value).map_err(|_| bad_value())?,
+            'n' => self.print_radix = value.parse().map_err(|_| bad_value())?,
+            _ => return Err(OptError::Unknown(flag)),
+        }
+        Ok(())
+    }
+
+    /// Checks that a loop does not iterate more times than allowed, and returns that count if so.
+    pub fn check_loop_iterations(&self, nb_iterations: i64) -> Result<u32, AsmErrorKind> {
+        u32::try_from(nb_iterations)
+            .ok()
+            .filter(|&nb_iterations| nb_iterations <= self.max_loop_iterations)
+            .ok_or(AsmErrorKind::TooManyIterations(
+                nb_iterations,
+                self.max_loop_iterations,
+            ))
+    }
+
+    /// Saves the current options, for [`Self::pop`] to restore later; this is `PUSHO`.
+    pub fn push(&mut self) {
+        let stack = std::mem::take(&mut self.stack);
+        let saved = self.clone();
+        self.stack = stack;
+        self.stack.push(saved);
+    }
+
+    /// Restores the options last saved by [`Self::push`]; this is `POPO`.
+    pub fn pop(&mut self) -> Result<(), AsmErrorKind> {
+        let saved = self.stack.pop().ok_or(AsmErrorKind::PopoWithoutPusho)?;
+        let stack = std::mem::take(&mut self.stack);
+        *self = saved;
+        self.stack = stack;
+        Ok(())
+    }
+}
+
+/// How `PRINT` formats numbers that are not given a format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrintRadix {
+    /// `$`-prefixed uppercase hexadecimal, which is what RGBDS has always done.
+    Hex,
+    /// Signed decimal.
+    Decimal,
+}
+
+impl PrintRadix {
+    pub fn format(self, value: i32) -> String {
+        match self {
+            Self::Hex => format!("${:X}", value as u32),
+            Self::Decimal => value.to_string(),
+        }
+    }
+}
+
+/// Parsed from the radix itself, i.e. `16` or `10`.
+impl FromStr for PrintRadix {
+    type Err = BadRadix;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match parse_number::<u32>(s) {
+            Ok(16) => Ok(Self::Hex),
+            Ok(10) => Ok(Self::Decimal),
+            _ => Err(BadRadix),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BadRadix;
+
+impl Display for BadRadix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("expected 16 (hexadecimal) or 10 (decimal)")
+    }
+}
+
+impl std::error::Error for BadRadix {}
+
+#[derive(Debug, Display)]
+pub enum OptError {
+    #[display("Empty option")]
+    Empty,
+    #[display("Unknown option '{0}'")]
+    Unknown(char),
+    #[display("Invalid value for option '{0}' in \"{1}\"")]
+    BadValue(char, SourceString),
+}
+
+/// Parses a number the same way C's `strtoul` does with a base of 0, which is how RGBDS parses
+/// numeric command-line arguments: `0x` prefixes hexadecimal, a leading `0` octal, and the rest is
+/// decimal.
+pub fn parse_number<T: TryFrom<u32>>(string: &str) -> Result<T, BadNumber> {
+    let (digits, radix) = if let Some(hex) = string
+        .strip_prefix("0x")
+        .or_else(|| string.strip_prefix("0X"))
+    {
+        (hex, 16)
+    } else if string.len() > 1 && string.starts_with('0') {
+        (&string[1..], 8)
+    } else {
+        (string, 10)
+    };
+
+    // `from_str_radix` accepts a leading sign, which `strtoul` would treat differently.
+    if !digits.starts_with(|c: char| c.is_digit(radix)) {
+        return Err(BadNumber);
+    }
+    u32::from_str_radix(digits, radix)
+        .ok()
+        .and_then(|value| value.try_into().ok())
+        .ok_or(BadNumber)
+}
+
+/// Parses the value of `OPT p`, which RGBDS reads as one or two hexadecimal digits, without any prefix.
+fn parse_hex_byte(string: &str) -> Option<u8> {
+    if !(1..=2).contains(&string.len()) || !string.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    u8::from_str_radix(string, 16).ok()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BadNumber;
+
+impl Display for BadNumber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("expected a decimal, octal (`0` prefix), or hexadecimal (`0x` prefix) number")
+    }
+}
+
+impl std::error::Error for BadNumber {}
+
+#[cfg(test)]
+mod tests {
+    use super::{Options, PrintRadix};
+    use crate::asm::{assemble::assemble_str, test_utils::assemble_with};
+
+    #[test]
+    fn opt_pad_byte() {
+        let (diagnostics, data) = assemble_with(
+            "opt_p.asm",
+            "SECTION \"a\", ROM0\nds 2\nOPT pFF\nds 2\nOPT p0xFF\nOPT q\nds 1\n",
+            |_, _| {},
+            |sections| sections.active_section().unwrap().data().to_vec(),
+        );
+        assert_eq!(data, [0x00, 0x00, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(
+            diagnostics,
+            r#"warning[-Winvalid-opt]: Invalid value for option 'p' in "p0xFF"
+  ┌─ opt_p.asm:5:5
+  │
+5 │ OPT p0xFF
+  │     ^^^^^
+
+warning[-Winvalid-opt]: Unknown option 'q'
+  ┌─ opt_p.asm:6:5
+  │
+6 │ OPT q
+  │     ^
+
+"#
+        );
+    }
+
+    #[test]
+    fn print_radix() {
+        let print = |source: &str, options: &Options| {
+            let result = assemble_str(source, options, |_| None, |_, _| ());
+            assert_eq!(result.diagnostics, Vec::<String>::new());
+            result.printed
+        };
+        let source = "PRINTLN 42, \" \", -1\n";
+
+        assert_eq!(print(source, &Options::default()), "$2A $FFFFFFFF\n");
+        let decimal = Options {
+            print_radix: PrintRadix::Decimal,
+            ..Options::default()
+        };
+        assert_eq!(print(source, &decimal), "42 -1\n");
+
+        let source = "PRINTLN 42\nPUSHO\nOPT n10\nPRINTLN 42\nPOPO\nPRINTLN 42, \"!\"\n";
+        assert_eq!(print(source, &Options::default()), "$2A\n42\n$2A!\n");
+    }
+}
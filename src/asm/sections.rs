@@ -1,31 +1,41 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt::Write};
 
-use rgbds::{
+use crate::{
     rpn::Rpn,
     section::{Kind, Modifier},
     RelocKind, TruncationLevel,
 };
 use string_interner::{backend::StringBackend, symbol::SymbolU32, StringInterner};
 
-use crate::{
+use crate::asm::{
+    error::json_str,
     expr::{ByteOrExpr, Expression},
-    fstack::{DiagInfo, Fstack},
+    fstack::Fstack,
     input::SourceString,
-    language::{AsmError, AsmErrorKind, Location, Warning},
+    language::{AsmError, AsmErrorKind, Location, Span, Warning},
     macro_args::MacroArgs,
     symbols::Symbols,
 };
 
+/// `ds` reserving at least this many bytes of ROM trips `-Wlarge-ds`.
+pub const LARGE_DS_LEN: usize = 256;
+
 #[derive(Debug)]
 pub struct Sections<'fstack> {
     names: StringInterner<StringBackend<SymbolU32>>,
     sections: HashMap<SymbolU32, SectionData<'fstack>>,
-    stack: Vec<Option<ActiveSection>>,
+    stack: Vec<Option<ActiveSection<'fstack>>>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct SectionId(SymbolU32);
 
+impl Default for Sections<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<'fstack> Sections<'fstack> {
     pub fn new() -> Self {
         let mut stack = Vec::with_capacity(2); // I have never seen nested `PUSHS`.
@@ -47,38 +57,212 @@ impl<'fstack> Sections<'fstack> {
         def_begin: Location<'fstack>,
         def_end: Location<'fstack>,
     ) -> Result<(), AsmError<'fstack>> {
-        use std::collections::hash_map::Entry;
+        if let Some(Some(active)) = self.stack.last() {
+            if !active.union_stack.is_empty() {
+                return Err(AsmError::new(
+                    def_begin,
+                    def_end,
+                    AsmErrorKind::SectionInUnion,
+                ));
+            }
+        }
+
+        // TODO: warn about the unterminated `LOAD` block
+        let _ = self.end_load();
+
+        let (name, offset) =
+            self.enter_section(name_string, kind, modifier, attrs, def_begin, def_end)?;
+
+        // Make the section "active".
+        *self.stack.last_mut().unwrap() = Some(ActiveSection::new(name, offset));
+
+        Ok(())
+    }
+
+    /// Starts a `LOAD` block: until the matching `ENDL`, data keeps being written to the active
+    /// section, but labels are defined in the "load" section instead.
+    pub fn start_load(
+        &mut self,
+        name_string: SourceString,
+        kind: Kind,
+        modifier: Modifier,
+        attrs: NormalizedSectAttrs,
+        def_begin: Location<'fstack>,
+        def_end: Location<'fstack>,
+    ) -> Result<(), AsmError<'fstack>> {
+        let err_kind = match self.stack.last().unwrap() {
+            None => Some(AsmErrorKind::LoadOutsideSection),
+            Some(active) if active.pc_section.is_some() => Some(AsmErrorKind::NestedLoad),
+            Some(_) if kind.has_data() => Some(AsmErrorKind::RomLoad(kind)),
+            Some(_) => None,
+        };
+        if let Some(kind) = err_kind {
+            return Err(AsmError::new(def_begin, def_end, kind));
+        }
+
+        let (name, offset) = self.enter_section(
+            name_string,
+            kind,
+            modifier,
+            attrs,
+            def_begin.clone(),
+            def_end.clone(),
+        )?;
+
+        let active = self.stack.last_mut().unwrap().as_mut().unwrap();
+        active.pc_section = Some(name);
+        active.pc_offset = offset;
+        active.load_def = Some((def_begin, def_end));
+        Ok(())
+    }
 
-        // TODO: bail if any UNION is active
+    /// Ends the active `LOAD` block.
+    pub fn end_load(&mut self) -> Result<(), AsmErrorKind> {
+        let Some(active) = self.stack.last_mut().unwrap() else {
+            return Err(AsmErrorKind::EndlWithoutLoad);
+        };
+        if active.pc_section.is_none() {
+            return Err(AsmErrorKind::EndlWithoutLoad);
+        }
+        if active.union_stack.last().is_some_and(|union| union.in_load) {
+            return Err(AsmErrorKind::EndlInUnion);
+        }
+        let load_section = active.pc_section.take().unwrap();
+        active.load_def = None;
+
+        // The load section is as large as the largest block that went into it.
+        let section = self.sections.get_mut(&load_section).unwrap();
+        section.len_virt = section.len_virt.max(active.pc_offset);
+        active.pc_offset = active.offset;
+        Ok(())
+    }
+
+    /// Starts a `UNION` block in the section that labels are being defined in: this is the `LOAD`
+    /// section if inside of a `LOAD` block, so that its data keeps going to the active section.
+    pub fn start_union(
+        &mut self,
+        def_begin: Location<'fstack>,
+        def_end: Location<'fstack>,
+    ) -> Result<(), AsmError<'fstack>> {
+        let Some(active) = self.stack.last_mut().unwrap() else {
+            return Err(AsmError::new(
+                def_begin,
+                def_end,
+                AsmErrorKind::UnionOutsideSection,
+            ));
+        };
+        let kind = self.sections[&active.pc_section.unwrap_or(active.name)].kind;
+        if kind.has_data() {
+            return Err(AsmError::new(
+                def_begin,
+                def_end,
+                AsmErrorKind::UnionInRom(kind),
+            ));
+        }
+
+        active.union_stack.push(Union {
+            def: (def_begin, def_end),
+            start_ofs: active.pc_offset,
+            len: 0,
+            in_load: active.pc_section.is_some(),
+        });
+        Ok(())
+    }
+
+    /// Ends the active `UNION` block's current member, and starts the next one at the same offset.
+    pub fn next_union(&mut self) -> Result<(), AsmErrorKind> {
+        let active = self.union_section("NEXTU")?;
+        let union = active.union_stack.last_mut().unwrap();
+        union.len = union.len.max(active.pc_offset - union.start_ofs);
+        let start_ofs = union.start_ofs;
+        active.set_pc_offset(start_ofs);
+        Ok(())
+    }
+
+    /// Ends the active `UNION` block; its size is that of its largest member.
+    pub fn end_union(&mut self) -> Result<(), AsmErrorKind> {
+        let active = self.union_section("ENDU")?;
+        let union = active.union_stack.pop().unwrap();
+        let len = union.len.max(active.pc_offset - union.start_ofs);
+        active.set_pc_offset(union.start_ofs + len);
+        Ok(())
+    }
+
+    /// Returns the active section, checking that it has a `UNION` block for `directive` to act on,
+    /// and that the block does not straddle a `LOAD` block's boundary.
+    fn union_section(
+        &mut self,
+        directive: &'static str,
+    ) -> Result<&mut ActiveSection<'fstack>, AsmErrorKind> {
+        let Some(Some(active)) = self.stack.last_mut() else {
+            return Err(AsmErrorKind::NoActiveUnion(directive));
+        };
+        match active.union_stack.last() {
+            None => Err(AsmErrorKind::NoActiveUnion(directive)),
+            Some(union) if union.in_load != active.pc_section.is_some() => {
+                Err(AsmErrorKind::UnionAcrossLoad(directive))
+            }
+            Some(_) => Ok(active),
+        }
+    }
+
+    /// To be called once the end of input is reached; errors out for each `UNION` or `LOAD` block
+    /// that is still open, pointing at the directive that opened it.
+    pub fn end_of_input(&mut self) -> Vec<AsmError<'fstack>> {
+        let mut errors = vec![];
+        // Close the blocks from the innermost outwards, as the matching directives would have.
+        while let Some(Some(active)) = self.stack.last() {
+            let in_load = active.pc_section.is_some();
+            let (def, kind) = match (active.union_stack.last(), &active.load_def) {
+                (Some(union), _) if union.in_load == in_load => {
+                    (union.def.clone(), AsmErrorKind::UnterminatedUnion)
+                }
+                (_, Some(load_def)) => (load_def.clone(), AsmErrorKind::UnterminatedLoad),
+                (_, None) => break,
+            };
+            let closed = match kind {
+                AsmErrorKind::UnterminatedUnion => self.end_union(),
+                _ => self.end_load(),
+            };
+            closed.expect("The innermost block should be closable");
+            errors.push(AsmError::new(def.0, def.1, kind));
+        }
+        errors
+    }
+
+    /// Looks up a section for (re-)entering it, creating it if it doesn't exist yet.
+    /// Returns its ID, and the offset at which entering it starts.
+    fn enter_section(
+        &mut self,
+        name_string: SourceString,
+        kind: Kind,
+        modifier: Modifier,
+        attrs: NormalizedSectAttrs,
+        def_begin: Location<'fstack>,
+        def_end: Location<'fstack>,
+    ) -> Result<(SymbolU32, usize), AsmError<'fstack>> {
+        use std::collections::hash_map::Entry;
 
         let name = self.names.get_or_intern(&name_string);
         let offset = match self.sections.entry(name) {
             Entry::Occupied(mut entry) => {
-                fn conflict<F: FnOnce(DiagInfo) -> AsmErrorKind>(
-                    other_def: &(Location<'_>, Location<'_>),
-                    err_constructor: F,
-                ) -> Result<usize, AsmErrorKind> {
-                    let other_def_info = Fstack::make_diag_info(&other_def.0, Some(&other_def.1));
-                    Err(err_constructor(other_def_info))
-                }
-
                 let other = entry.get_mut();
+                let prev_def =
+                    Fstack::make_diag_info(&other.definition.0, Some(&other.definition.1));
                 match other.modifier {
-                    Modifier::Normal => conflict(&other.definition, |other_def_info| {
-                        AsmErrorKind::SectAlreadyDefined(name_string, other_def_info)
-                    }),
-                    _ if other.modifier != modifier => {
-                        conflict(&other.definition, |other_def_info| {
-                            AsmErrorKind::DifferentSectMod(
-                                name_string,
-                                other.modifier,
-                                other_def_info,
-                            )
-                        })
+                    Modifier::Normal => {
+                        Err(AsmErrorKind::SectAlreadyDefined(name_string, prev_def))
                     }
-                    _ if other.kind != kind => conflict(&other.definition, |other_def_info| {
-                        AsmErrorKind::DifferentSectKind(name_string, other.kind, other_def_info)
-                    }),
+                    _ if other.modifier != modifier => Err(AsmErrorKind::DifferentSectMod(
+                        name_string,
+                        other.modifier,
+                        prev_def,
+                    )),
+                    _ if other.kind != kind => Err(AsmErrorKind::DifferentSectKind(
+                        name_string,
+                        other.kind,
+                        prev_def,
+                    )),
                     Modifier::Union => {
                         if kind.has_data() {
                             Err(AsmErrorKind::RomUnion(kind))
@@ -88,11 +272,10 @@ impl<'fstack> Sections<'fstack> {
                         }
                     }
                     Modifier::Fragment => {
-                        // len_virt, or real len?
-                        other
-                            .attrs
-                            .concat_fragments(name_string, &attrs)
-                            .map(|()| todo!())
+                        // TODO: len_virt, or real len?
+                        other.attrs.concat_fragments(name_string, &attrs).and(Err(
+                            AsmErrorKind::Unsupported("Defining a section fragment more than once"),
+                        ))
                     }
                 }
                 .map_err(|kind| AsmError {
@@ -114,10 +297,73 @@ impl<'fstack> Sections<'fstack> {
             }
         }?;
 
-        // Make the section "active".
-        *self.stack.last_mut().unwrap() = Some(ActiveSection::new(name, offset));
+        Ok((name, offset))
+    }
 
-        Ok(())
+    pub fn name_of(&self, id: SectionId) -> &str {
+        self.names.resolve(id.0).unwrap()
+    }
+
+    /// Returns the section's address, if it is already known.
+    pub fn try_get_addr(&self, id: SectionId) -> Option<u16> {
+        self.sections[&id.0].attrs.address
+    }
+
+    /// Iterates over the sections whose address and bank are both known, sorted by bank and then
+    /// address, and then in definition order. Floating sections are skipped, as only the linker
+    /// can place them.
+    pub fn placed(&self) -> impl Iterator<Item = PlacedSection<'_>> {
+        // Starting from definition order and sorting stably keeps the output reproducible;
+        // `self.sections`' iteration order is not even the same between two runs.
+        let mut placed: Vec<_> = self
+            .iter()
+            .filter_map(|(name, section)| {
+                Some(PlacedSection {
+                    name,
+                    kind: section.kind,
+                    address: section.attrs.address?,
+                    bank: section.attrs.bank?,
+                    data: &section.data,
+                })
+            })
+            .collect();
+        placed.sort_by_key(|section| (section.bank, section.address));
+        placed.into_iter()
+    }
+
+    /// Iterates over every section, in the order they were first defined.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &SectionData<'fstack>)> {
+        self.names
+            .into_iter()
+            .filter_map(|(name, string)| Some((string, self.sections.get(&name)?)))
+    }
+
+    /// Dumps the section table as a JSON array, one section per line and in definition order.
+    /// Attributes that are left for the linker to decide are `null`.
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("[");
+        for (i, (name, section)) in self.iter().enumerate() {
+            json.push_str(if i == 0 { "\n" } else { ",\n" });
+            json.push_str("{\"name\":");
+            json_str(&mut json, name);
+            let opt = |value: Option<u32>| value.map_or("null".into(), |value| value.to_string());
+            let (alignment, align_offset) = section.alignment();
+            write!(
+                json,
+                ",\"kind\":\"{}\",\"modifier\":\"{}\",\"address\":{},\"bank\":{},\"alignment\":{},\"align_offset\":{},\"fill\":{},\"size\":{}}}",
+                section.kind(),
+                section.modifier().to_string().to_lowercase(),
+                opt(section.address().map(u32::from)),
+                opt(section.bank()),
+                alignment,
+                align_offset,
+                opt(section.fill().map(u32::from)),
+                section.len(),
+            )
+            .unwrap();
+        }
+        json.push_str("\n]\n");
+        json
     }
 
     pub fn active_section<'a>(&'a self) -> Option<SectionHandle<'a, 'fstack>> {
@@ -128,6 +374,44 @@ impl<'fstack> Sections<'fstack> {
         ))
     }
 
+    /// Looks up a section by name; returns `None` if no such section has been defined.
+    pub fn get_by_name(&self, name: &str) -> Option<&SectionData<'fstack>> {
+        self.sections.get(&self.names.get(name)?)
+    }
+
+    /// Looks up a section by name; returns `None` if no such section has been defined.
+    pub fn get_by_name_mut(&mut self, name: &str) -> Option<&mut SectionData<'fstack>> {
+        self.sections.get_mut(&self.names.get(name)?)
+    }
+
+    /// The value of `STARTOF(name)`, if it is already known, i.e. if the section has a fixed address.
+    pub fn start_of(&self, name: &str) -> Option<u16> {
+        self.get_by_name(name)?.address()
+    }
+
+    /// The value of `SIZEOF(name)`, if it is already known, i.e. if the section has a fixed address
+    /// and cannot grow anymore.
+    pub fn size_of(&self, name: &str) -> Option<usize> {
+        let id = self.names.get(name)?;
+        let section = &self.sections[&id];
+        // Unions and fragments can be added to by a later definition, and plain sections while
+        // they're active (or `PUSHS`ed).
+        let is_open = section.modifier != Modifier::Normal
+            || self
+                .stack
+                .iter()
+                .flatten()
+                .any(|active| active.name == id || active.pc_section == Some(id));
+        (section.attrs.address.is_some() && !is_open).then_some(section.len_virt)
+    }
+
+    /// Records that a label has been defined in the active section, if any.
+    pub fn note_label(&mut self) {
+        if let Some(Some(active)) = self.stack.last_mut() {
+            active.labeled = true;
+        }
+    }
+
     pub fn active_section_mut<'a>(&'a mut self) -> Option<SectionHandleMut<'a, 'fstack>> {
         let top_slot = self.stack.last_mut().and_then(|slot| slot.as_mut())?;
         Some(SectionHandleMut(
@@ -137,6 +421,17 @@ impl<'fstack> Sections<'fstack> {
     }
 }
 
+/// A section whose final location is known, as yielded by [`Sections::placed`].
+#[derive(Debug, Clone, Copy)]
+pub struct PlacedSection<'a> {
+    pub name: &'a str,
+    pub kind: Kind,
+    pub address: u16,
+    pub bank: u32,
+    /// Empty for sections that cannot contain data.
+    pub data: &'a [u8],
+}
+
 #[derive(Debug)]
 pub struct SectionData<'fstack> {
     kind: Kind,
@@ -158,7 +453,7 @@ impl<'fstack> SectionData<'fstack> {
         definition: (Location<'fstack>, Location<'fstack>),
         attrs: NormalizedSectAttrs,
     ) -> Self {
-        Self {
+        let mut section = Self {
             kind,
             modifier,
             definition,
@@ -167,7 +462,78 @@ impl<'fstack> SectionData<'fstack> {
             patches: vec![],
             data: vec![],
             len_virt: 0,
+        };
+        // The data can never outgrow this, so this avoids reallocating it while it's being emitted.
+        // (Memory that ends up unused is usually never actually committed by the OS, either.)
+        if kind.has_data() {
+            section.data.reserve_exact(section.capacity());
         }
+        section
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    /// Where the section was first defined.
+    pub fn definition(&self) -> &(Location<'fstack>, Location<'fstack>) {
+        &self.definition
+    }
+
+    pub fn modifier(&self) -> Modifier {
+        self.modifier
+    }
+
+    pub fn address(&self) -> Option<u16> {
+        self.attrs.address
+    }
+
+    pub fn bank(&self) -> Option<u32> {
+        self.attrs.bank
+    }
+
+    /// The section's alignment, as a number of bits and the offset from that alignment.
+    pub fn alignment(&self) -> (u8, u16) {
+        (self.attrs.alignment, self.attrs.align_offset)
+    }
+
+    /// Whether the section must be kept even if nothing references it.
+    pub fn keep(&self) -> bool {
+        self.attrs.keep
+    }
+
+    /// The byte that the section is padded with, if it overrides the global one.
+    pub fn fill(&self) -> Option<u8> {
+        self.attrs.fill
+    }
+
+    /// Empty for sections that cannot contain data.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The section's size, which is also tracked for sections that cannot contain data.
+    pub fn len(&self) -> usize {
+        self.len_virt
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len_virt == 0
+    }
+
+    /// How many bytes the section can hold, which is less if it starts further into its region.
+    fn capacity(&self) -> usize {
+        let size = usize::from(self.kind.size(true, true));
+        match self.attrs.address {
+            Some(addr) => size.saturating_sub(addr.saturating_sub(self.kind.start_addr()).into()),
+            None => size,
+        }
+    }
+
+    /// Iterates over the section's patches, as their offset into the section and their kind.
+    #[cfg(test)]
+    pub fn patches(&self) -> impl Iterator<Item = (usize, RelocKind)> + '_ {
+        self.patches.iter().map(|patch| (patch.offset, patch.kind))
     }
 }
 
@@ -185,17 +551,21 @@ struct Relocation<'fstack> {
 }
 
 #[derive(Debug)]
-struct ActiveSection {
+struct ActiveSection<'fstack> {
     name: SymbolU32,
 
     offset: usize,
     pc_section: Option<SymbolU32>,
     pc_offset: usize,
+    /// Where the active `LOAD` block was started, if any.
+    load_def: Option<Span<'fstack>>,
     label_scope: Option<SymbolU32>,
-    union_stack: Vec<Union>,
+    /// Whether any label has been defined since the section was made active, for `-Wunlabeled-data`.
+    labeled: bool,
+    union_stack: Vec<Union<'fstack>>,
 }
 
-impl ActiveSection {
+impl ActiveSection<'_> {
     fn new(name: SymbolU32, offset: usize) -> Self {
         Self {
             name,
@@ -203,23 +573,62 @@ impl ActiveSection {
             offset,
             pc_section: None,
             pc_offset: 0,
+            load_def: None,
             label_scope: None,
+            labeled: false,
             union_stack: vec![],
         }
     }
+
+    /// Moves PC, e.g. back to the beginning of a `UNION`. Outside of `LOAD` blocks, PC is the
+    /// active section's offset as well.
+    fn set_pc_offset(&mut self, pc_offset: usize) {
+        self.pc_offset = pc_offset;
+        if self.pc_section.is_none() {
+            self.offset = pc_offset;
+        }
+    }
 }
 
-pub struct SectionHandle<'a, 'fstack>(&'a ActiveSection, &'a SectionData<'fstack>);
+pub struct SectionHandle<'a, 'fstack>(&'a ActiveSection<'fstack>, &'a SectionData<'fstack>);
 
 impl<'fstack> SectionHandle<'_, 'fstack> {
+    /// The section that labels defined at this point belong to; this is not necessarily the active
+    /// section itself, due to `LOAD` blocks.
+    pub fn pc_section(&self) -> SectionId {
+        SectionId(self.0.pc_section.unwrap_or(self.0.name))
+    }
+
+    pub fn pc_offset(&self) -> usize {
+        self.0.pc_offset
+    }
+
     pub fn try_get_pc(&self) -> Option<u16> {
         self.1.attrs.address.map(|base_addr| {
             base_addr.wrapping_add(self.1.data.len().try_into().unwrap_or(u16::MAX))
         })
     }
+
+    #[cfg(test)]
+    pub fn data(&self) -> &[u8] {
+        &self.1.data
+    }
+
+    #[cfg(test)]
+    pub fn nb_patches(&self) -> usize {
+        self.1.patches.len()
+    }
+
+    #[cfg(test)]
+    pub fn patch_offsets(&self) -> Vec<usize> {
+        self.1.patches.iter().map(|patch| patch.offset).collect()
+    }
 }
 
-pub struct SectionHandleMut<'a, 'fstack>(&'a mut ActiveSection, &'a mut SectionData<'fstack>);
+pub struct SectionHandleMut<'a, 'fstack>(
+    &'a mut ActiveSection<'fstack>,
+    &'a mut SectionData<'fstack>,
+);
 
 impl<'fstack> SectionHandleMut<'_, 'fstack> {
     pub fn extend<
@@ -237,12 +646,11 @@ impl<'fstack> SectionHandleMut<'_, 'fstack> {
         let total_len = slice.as_ref().iter().fold(0, |len, item| {
             len + match item {
                 ByteOrExpr::Byte(_) => 1,
-                ByteOrExpr::Expr(_, _, _, expr_kind) => expr_kind.width(),
+                ByteOrExpr::Expr(_, _, _, expr_kind) => usize::from(expr_kind.width()),
             }
         });
-        self.1.len_virt = self.1.len_virt.saturating_add(total_len.into());
-
-        if self.1.len_virt <= self.1.kind.size(true, true).into() {
+        let (mut offset, mut pc_offset) = (self.0.offset, self.0.pc_offset);
+        if self.grow(total_len)? {
             for item in slice.into_iter() {
                 let len = match item {
                     ByteOrExpr::Byte(byte) => {
@@ -261,9 +669,11 @@ impl<'fstack> SectionHandleMut<'_, 'fstack> {
                                     warn(Warning {
                                         begin,
                                         end,
-                                        kind: crate::language::WarningKind::Truncation {
+                                        kind: crate::asm::language::WarningKind::Truncation {
                                             level,
                                             width: kind.width() * 8,
+                                            value: constant,
+                                            range: kind.range(),
                                         },
                                     });
                                 }
@@ -273,9 +683,9 @@ impl<'fstack> SectionHandleMut<'_, 'fstack> {
                             None => {
                                 self.1.patches.push(Relocation {
                                     definition: (begin, end),
-                                    offset: self.0.offset,
+                                    offset,
                                     pc_section: self.0.pc_section,
-                                    pc_offset: self.0.pc_offset,
+                                    pc_offset,
                                     kind,
                                     rpn,
                                 });
@@ -289,8 +699,8 @@ impl<'fstack> SectionHandleMut<'_, 'fstack> {
                 };
 
                 // Advance the offset.
-                self.0.offset += usize::from(len);
-                self.0.pc_offset += usize::from(len);
+                offset += usize::from(len);
+                pc_offset += usize::from(len);
             }
             debug_assert_eq!(self.1.len_virt, self.1.data.len());
         }
@@ -298,10 +708,107 @@ impl<'fstack> SectionHandleMut<'_, 'fstack> {
     }
 }
 
+impl SectionHandleMut<'_, '_> {
+    pub fn has_data(&self) -> bool {
+        self.1.kind.has_data()
+    }
+
+    pub fn is_labeled(&self) -> bool {
+        self.0.labeled
+    }
+
+    /// Appends raw bytes to the section, such as an `INCBIN`ed file's.
+    pub fn extend_bytes(&mut self, bytes: &[u8]) -> Result<(), AsmErrorKind> {
+        if !self.1.kind.has_data() {
+            return Err(AsmErrorKind::NotCodeSection(self.1.kind));
+        }
+
+        if self.grow(bytes.len())? {
+            self.1.data.extend_from_slice(bytes);
+            debug_assert_eq!(self.1.len_virt, self.1.data.len());
+        }
+        Ok(())
+    }
+
+    /// Reserves some space in the section; if it can contain data, the space is filled with the
+    /// section's `FILL` byte, or `pad_byte` if it has none.
+    pub fn reserve(&mut self, len: usize, pad_byte: u8) -> Result<(), AsmErrorKind> {
+        if self.grow(len)? && self.1.kind.has_data() {
+            let fill = self.1.attrs.fill.unwrap_or(pad_byte);
+            self.1.data.resize(self.1.data.len() + len, fill);
+            debug_assert_eq!(self.1.len_virt, self.1.data.len());
+        }
+        Ok(())
+    }
+
+    /// Pads the section until its next byte satisfies `ALIGN[alignment, offset]`, for `DS ALIGN`.
+    /// A floating section is only padded as far as its own alignment allows; its alignment is then
+    /// tightened, so that the linker takes care of the rest.
+    pub fn pad_to_alignment(
+        &mut self,
+        alignment: u8,
+        offset: u16,
+        pad_byte: u8,
+    ) -> Result<(), AsmErrorKind> {
+        // Sections with a fixed address count as being maximally aligned.
+        let (base, cur_alignment) = match self.1.attrs.address {
+            Some(addr) => (addr, 16),
+            None => (self.1.attrs.align_offset, self.1.attrs.alignment),
+        };
+        let mask = |alignment: u8| (1u32 << alignment) - 1;
+        let cur_ofs = self.0.offset as u32;
+        let len = u32::from(offset).wrapping_sub(u32::from(base).wrapping_add(cur_ofs))
+            & mask(alignment.min(cur_alignment));
+        self.reserve(len as usize, pad_byte)?;
+
+        if self.1.attrs.address.is_none() && alignment > cur_alignment {
+            let cur_ofs = self.0.offset as u32;
+            self.1.attrs.alignment = alignment;
+            self.1.attrs.align_offset =
+                (u32::from(offset).wrapping_sub(cur_ofs) & mask(alignment)) as u16;
+        }
+        Ok(())
+    }
+
+    /// Accounts for `len` more bytes in the section, moving past them, and returns whether they fit
+    /// in it.
+    /// Only the output that makes the section overflow is reported, not any that comes after it;
+    /// unless it is so large that the section's size cannot even be represented anymore.
+    fn grow(&mut self, len: usize) -> Result<bool, AsmErrorKind> {
+        // Unions can move the offset back, so the output does not necessarily go past the end.
+        let prev_len = self.0.offset;
+        let new_len = prev_len
+            .checked_add(len)
+            .ok_or(AsmErrorKind::SectionSizeOverflow(len))?;
+        self.1.len_virt = self.1.len_virt.max(new_len);
+        self.0.offset = new_len;
+        self.0.pc_offset = self.0.pc_offset.saturating_add(len);
+
+        let capacity = self.1.capacity();
+        if new_len <= capacity {
+            Ok(true)
+        } else if prev_len <= capacity {
+            Err(AsmErrorKind::SectionOverflow(
+                capacity - prev_len,
+                len,
+                capacity,
+            ))
+        } else {
+            Ok(false)
+        }
+    }
+}
+
 #[derive(Debug)]
-struct Union {
+struct Union<'fstack> {
+    /// Where the block was started, for reporting it if it is left unterminated.
+    def: Span<'fstack>,
+    /// Offset of PC at the beginning of the block, which each member starts back from.
     start_ofs: usize,
+    /// Size of the largest member so far, not counting the current one.
     len: usize,
+    /// Whether the block was started inside of a `LOAD` block; it must then end inside of it too.
+    in_load: bool,
 }
 
 #[derive(Debug, Default)]
@@ -309,6 +816,8 @@ pub struct SectionAttributes<'fstack> {
     pub(crate) bank: Option<Expression<'fstack>>,
     pub(crate) alignment: Option<Expression<'fstack>>,
     pub(crate) offset: Option<Expression<'fstack>>,
+    pub(crate) keep: bool,
+    pub(crate) fill: Option<Expression<'fstack>>,
 }
 
 #[derive(Debug)]
@@ -317,6 +826,10 @@ pub struct NormalizedSectAttrs {
     bank: Option<u32>,
     alignment: u8,
     align_offset: u16,
+    /// Whether the linker must retain the section even if nothing references it.
+    keep: bool,
+    /// What to pad the section with instead of the global pad byte, if anything.
+    fill: Option<u8>,
 }
 
 impl NormalizedSectAttrs {
@@ -421,6 +934,16 @@ impl NormalizedSectAttrs {
             sections,
         )?
         .unwrap_or(0);
+        let fill = eval(
+            attrs.fill,
+            |fill| {
+                fill.try_into()
+                    .map_err(|_| AsmErrorKind::FillOutOfRange(fill))
+            },
+            symbols,
+            macro_args,
+            sections,
+        )?;
 
         // Now, perform some more checks.
 
@@ -463,6 +986,8 @@ impl NormalizedSectAttrs {
                 bank,
                 alignment,
                 align_offset,
+                keep: attrs.keep,
+                fill,
             },
             // "Return" the locations, since they weren't used.
             def_begin,
@@ -483,6 +1008,18 @@ impl NormalizedSectAttrs {
             (None, other_bank) => self.bank = other_bank,
             (Some(_), None) => {} // The current constraint is stronger.
         }
+        // If any piece must be kept, then so must the whole section.
+        self.keep |= other.keep;
+        // Like the bank, the fill byte may be left unspecified by some pieces.
+        match (self.fill, other.fill) {
+            (Some(current), Some(new)) => {
+                if current != new {
+                    return Err(AsmErrorKind::DifferentFill(name, current, new));
+                }
+            }
+            (None, other_fill) => self.fill = other_fill,
+            (Some(_), None) => {}
+        }
 
         Ok(name)
     }
@@ -491,16 +1028,683 @@ impl NormalizedSectAttrs {
         let name = self.merge(name, other)?;
 
         // Address-wise, any "compatible" constraints are acceptable, and we end up with the strictest.
-        todo!();
+        let mask = |alignment: u8| (1u16 << alignment).wrapping_sub(1);
+        match (self.address, other.address) {
+            (Some(current), Some(new)) => {
+                if current != new {
+                    return Err(AsmErrorKind::DifferentAddr(name, current, new));
+                }
+            }
+            (Some(current), None) => {
+                if current & mask(other.alignment) != other.align_offset {
+                    return Err(AsmErrorKind::AlignMismatch(
+                        current,
+                        other.alignment,
+                        other.align_offset,
+                    ));
+                }
+            }
+            (None, Some(new)) => {
+                if new & mask(self.alignment) != self.align_offset {
+                    return Err(AsmErrorKind::AlignMismatch(
+                        new,
+                        self.alignment,
+                        self.align_offset,
+                    ));
+                }
+                self.address = Some(new);
+                self.alignment = 0;
+                self.align_offset = 0;
+            }
+            (None, None) => {
+                // The stricter alignment must also satisfy the looser one.
+                let (looser, stricter) = if self.alignment <= other.alignment {
+                    (
+                        (self.alignment, self.align_offset),
+                        (other.alignment, other.align_offset),
+                    )
+                } else {
+                    (
+                        (other.alignment, other.align_offset),
+                        (self.alignment, self.align_offset),
+                    )
+                };
+                if stricter.1 & mask(looser.0) != looser.1 {
+                    return Err(AsmErrorKind::DifferentAlign(name));
+                }
+                (self.alignment, self.align_offset) = stricter;
+            }
+        }
 
         Ok(())
     }
 
     fn concat_fragments(&mut self, name: SourceString, other: &Self) -> Result<(), AsmErrorKind> {
-        let name = self.merge(name, other)?;
+        self.merge(name, other)?;
+        // TODO: the fragments' alignments must be reconciled.
+        Ok(())
+    }
+}
 
-        todo!();
+#[cfg(test)]
+mod tests {
+    use crate::{
+        section::{Kind, Modifier},
+        RelocKind,
+    };
 
-        Ok(())
+    use super::{NormalizedSectAttrs, Sections};
+    use crate::asm::{
+        error::WarningState,
+        language::{AsmErrorKind, Location, WarningId},
+        test_utils::{assemble, assemble_with, error_lines},
+    };
+
+    #[test]
+    fn truncation() {
+        let (diagnostics, ()) = assemble_with(
+            "trunc.asm",
+            "SECTION \"a\", ROM0\nDB $1FF\nDW -$8001\nDB -1, -$80, $FF\n",
+            |reporter, _| reporter.apply_warning_flag(&"truncation=2".parse().unwrap()),
+            |_| (),
+        );
+        assert_eq!(
+            diagnostics
+                .lines()
+                .filter(|line| line.starts_with("warning"))
+                .collect::<Vec<_>>(),
+            [
+                "warning[-Wtruncation=1]: Value $1FF does not fit in 8 bits (from -128 to 255, 256 too large)",
+                "warning[-Wtruncation=2]: Value -$8001 does not fit in 16 bits (from -32768 to 65535, 1 too small)",
+            ]
+        );
+    }
+
+    #[test]
+    fn mixed_data() {
+        let (diagnostics, (data, patch_offsets)) = assemble_with(
+            "data.asm",
+            "SECTION \"a\", ROM0\nStart:\ndb \"hi\", 0, Label, $FF\ndw \"A\", $1234, Label\n",
+            |_, _| {},
+            |sections| {
+                let section = sections.active_section().unwrap();
+                (section.data().to_vec(), section.patch_offsets())
+            },
+        );
+        assert_eq!(diagnostics, "");
+        assert_eq!(
+            data,
+            [b'h', b'i', 0x00, 0x00, 0xFF, b'A', 0x00, 0x34, 0x12, 0x00, 0x00]
+        );
+        // The references to `Label` come after the strings and constants.
+        assert_eq!(patch_offsets, [3, 9]);
+
+        let (diagnostics, ()) = assemble_with(
+            "data.asm",
+            "SECTION \"a\", ROM0\ndb 1\n",
+            |reporter, _| {
+                reporter.set_warning_state(WarningId::UnlabeledData, WarningState::Enabled)
+            },
+            |_| (),
+        );
+        assert!(diagnostics.starts_with("warning[-Wunlabeled-data]"));
+    }
+
+    #[test]
+    fn load_union() {
+        let (diagnostics, (rom_len, ram_len)) = assemble_with(
+            "load.asm",
+            "SECTION \"rom\", ROM0\nLOAD UNION \"ram\", WRAM0\nds 2\nENDL\nLOAD UNION \"ram\", WRAM0\nds 5\nENDL\nLOAD UNION \"ram\", WRAM0\nds 1\nENDL\n",
+            |_, _| {},
+            |sections| {
+                let len_of = |name| sections.get_by_name(name).map(|section| section.len());
+                (len_of("rom"), len_of("ram"))
+            },
+        );
+        assert_eq!(diagnostics, "");
+        // The data all goes to ROM, but each block starts over in RAM.
+        assert_eq!(rom_len, Some(8));
+        assert_eq!(ram_len, Some(5));
+
+        let (diagnostics, ()) = assemble_with(
+            "load.asm",
+            "SECTION \"rom\", ROM0\nENDL\nLOAD \"a\", WRAM0\nLOAD \"b\", WRAM0\nENDL\nENDL\n",
+            |_, _| {},
+            |_| (),
+        );
+        assert_eq!(
+            error_lines(&diagnostics),
+            [
+                "error: `ENDL` found outside of a `LOAD` block",
+                "error: `LOAD` blocks cannot be nested",
+                "error: `ENDL` found outside of a `LOAD` block",
+            ]
+        );
+    }
+
+    #[test]
+    fn union_in_load() {
+        let (diagnostics, (rom_len, ram_len)) = assemble_with(
+            "union.asm",
+            "SECTION \"rom\", ROM0\nLOAD UNION \"ram\", WRAM0\nUNION\nds 2\nNEXTU\nds 5\nNEXTU\nds 3\nENDU\nds 1\nENDL\n",
+            |_, _| {},
+            |sections| {
+                let len_of = |name| sections.get_by_name(name).map(|section| section.len());
+                (len_of("rom"), len_of("ram"))
+            },
+        );
+        assert_eq!(diagnostics, "");
+        // Every member's data goes to ROM, one after the other...
+        assert_eq!(rom_len, Some(11));
+        // ...but the load PC only moves past the largest one.
+        assert_eq!(ram_len, Some(6));
+
+        let (diagnostics, ()) = assemble_with(
+            "union.asm",
+            "SECTION \"rom\", ROM0\nLOAD \"a\", WRAM0\nUNION\nENDL\nENDU\nENDL\nSECTION \"ram\", WRAM0\nUNION\nLOAD \"b\", WRAM0\nNEXTU\nENDU\nENDL\nENDU\n",
+            |_, _| {},
+            |_| (),
+        );
+        assert_eq!(
+            error_lines(&diagnostics),
+            [
+                "error: `ENDL` found before the `ENDU` of a `UNION` started inside the `LOAD` block",
+                "error: `NEXTU` found before the `ENDL` of a `LOAD` block started inside the `UNION`",
+                "error: `ENDU` found before the `ENDL` of a `LOAD` block started inside the `UNION`",
+            ]
+        );
+    }
+
+    #[test]
+    fn ds_string() {
+        let (diagnostics, data) = assemble_with(
+            "ds_string.asm",
+            "SECTION \"a\", ROM0\nOPT pFF\nds 4, \"ab\"\nds 2, \"abc\"\nds 2, \"cd\"\n",
+            |_, _| {},
+            |sections| sections.active_section().unwrap().data().to_vec(),
+        );
+        assert_eq!(data, b"ab\xFF\xFFcd");
+        assert_eq!(
+            diagnostics,
+            r#"error: A 3-byte string does not fit in `ds 2`
+  ┌─ ds_string.asm:4:1
+  │
+4 │ ds 2, "abc"
+  │ ^^^^^^^^^^^
+
+"#
+        );
+    }
+
+    #[test]
+    fn unterminated_load() {
+        let (diagnostics, ram_len) = assemble_with(
+            "load.asm",
+            "SECTION \"rom\", ROM0\nLOAD \"ram\", WRAM0\nds 3\n",
+            |_, _| {},
+            |sections| sections.get_by_name("ram").map(|section| section.len()),
+        );
+        assert_eq!(
+            diagnostics,
+            r#"error: Unterminated `LOAD` block
+  ┌─ load.asm:2:1
+  │
+2 │ LOAD "ram", WRAM0
+  │ ^^^^^^^^^^^^^^^^^
+  │
+  = The input ended before the matching `ENDL`
+
+"#
+        );
+        // The block is still closed, as if by an `ENDL`.
+        assert_eq!(ram_len, Some(3));
+    }
+
+    #[test]
+    fn placed_sections() {
+        let (diagnostics, placed) = assemble_with(
+            "placed.asm",
+            "SECTION \"b\", ROMX[$4000], BANK[2]\nds 1\nSECTION \"floating\", ROM0\nds 3\nSECTION \"a\", ROM0[$150]\nOPT p11\nds 2\n",
+            |_, _| {},
+            |sections| {
+                sections
+                    .placed()
+                    .map(|section| {
+                        (section.name.to_string(), section.kind, section.address, section.bank, section.data.to_vec())
+                    })
+                    .collect::<Vec<_>>()
+            },
+        );
+        assert_eq!(diagnostics, "");
+        assert_eq!(
+            placed,
+            [
+                ("a".to_string(), Kind::Rom0, 0x150, 0, vec![0x11, 0x11]),
+                ("b".to_string(), Kind::Romx, 0x4000, 2, vec![0x00]),
+            ]
+        );
+    }
+
+    #[test]
+    fn placed_order_is_stable() {
+        let source: String = ["c", "a", "d", "b", "e"]
+            .iter()
+            .map(|name| format!("SECTION \"{name}\", WRAM0[$C000]\n"))
+            .collect();
+        let names = || {
+            assemble_with(
+                "stable.asm",
+                &source,
+                |_, _| {},
+                |sections| {
+                    sections
+                        .placed()
+                        .map(|section| section.name.to_string())
+                        .collect::<Vec<_>>()
+                },
+            )
+            .1
+        };
+        assert_eq!(names(), ["c", "a", "d", "b", "e"]);
+        assert_eq!(names(), names());
+    }
+
+    #[test]
+    fn keep() {
+        let (diagnostics, kept) = assemble_with(
+            "keep.asm",
+            concat!(
+                "SECTION \"vectors\", ROM0[0], KEEP\nreti\n",
+                // `KEEP` is only special as a section attribute.
+                "SECTION \"code\", ROM0\nKeep: nop\n",
+                "SECTION UNION \"vars\", WRAMX, BANK[1]\nds 1\n",
+                "SECTION UNION \"vars\", WRAMX, KEEP, ALIGN[1]\nds 2\n",
+            ),
+            |_, _| {},
+            |sections| {
+                sections
+                    .iter()
+                    .map(|(name, section)| (name.to_string(), section.keep()))
+                    .collect::<Vec<_>>()
+            },
+        );
+        assert_eq!(diagnostics, "");
+        assert_eq!(
+            kept,
+            [
+                ("vectors".to_string(), true),
+                ("code".to_string(), false),
+                ("vars".to_string(), true),
+            ]
+        );
+
+        assert_eq!(
+            assemble("keep.asm", "SECTION \"a\", ROM0, KEPT\n"),
+            r#"error: Unknown section attribute "KEPT"
+  ┌─ keep.asm:1:20
+  │
+1 │ SECTION "a", ROM0, KEPT
+  │                    ^^^^
+
+"#
+        );
+    }
+
+    #[test]
+    fn sizeof_startof() {
+        let (diagnostics, (data, patch_offsets)) = assemble_with(
+            "sizeof.asm",
+            concat!(
+                "SECTION \"Fixed\", ROM0[$100]\nds 3\n",
+                "SECTION \"Floating\", ROM0\nds 5\n",
+                "SECTION \"code\", ROM0\n",
+                "dw SIZEOF(\"Fixed\"), STARTOF(\"Fixed\") + 1\n",
+                "dw SIZEOF(\"Floating\"), STARTOF(\"Floating\")\n",
+                // The active section may still grow.
+                "dw SIZEOF(\"code\")\n",
+            ),
+            |_, _| {},
+            |sections| {
+                let section = sections.active_section().unwrap();
+                (section.data().to_vec(), section.patch_offsets())
+            },
+        );
+        assert_eq!(diagnostics, "");
+        assert_eq!(data[..4], [0x03, 0x00, 0x01, 0x01]);
+        assert_eq!(patch_offsets, [4, 6, 8]);
+    }
+
+    #[test]
+    fn sect_start() {
+        let (diagnostics, data) = assemble_with(
+            "sectstart.asm",
+            "SECTION \"Fixed\", ROM0[$150]\nds 2\ndw SECTSTART(0), SECTSTART(1) + 2\n",
+            |_, _| {},
+            |sections| sections.active_section().unwrap().data().to_vec(),
+        );
+        assert_eq!(diagnostics, "");
+        assert_eq!(data, [0x00, 0x00, 0x50, 0x01, 0x52, 0x01]);
+
+        let (diagnostics, (data, patch_offsets)) = assemble_with(
+            "sectstart.asm",
+            "SECTION \"Floating\", ROM0\ndw SECTSTART(1)\ndw SECTSTART(0)\n",
+            |_, _| {},
+            |sections| {
+                let section = sections.active_section().unwrap();
+                (section.data().to_vec(), section.patch_offsets())
+            },
+        );
+        assert_eq!(
+            diagnostics,
+            r#"error: Section "Floating" is floating, so its start address is not known yet
+  ┌─ sectstart.asm:3:4
+  │
+3 │ dw SECTSTART(0)
+  │    ^^^^^^^^^^^^
+
+"#
+        );
+        assert_eq!(data, [0x00, 0x00]);
+        assert_eq!(patch_offsets, [0]);
+    }
+
+    #[test]
+    fn section_json() {
+        let (diagnostics, json) = assemble_with(
+            "json.asm",
+            concat!(
+                "SECTION \"code\", ROM0[$150]\nds 3\n",
+                "SECTION FRAGMENT \"vars\", WRAMX, BANK[2], ALIGN[4, 2]\nds 5\n",
+            ),
+            |_, _| {},
+            |sections| sections.to_json(),
+        );
+        assert_eq!(diagnostics, "");
+        assert_eq!(
+            json,
+            r#"[
+{"name":"code","kind":"ROM0","modifier":"normal","address":336,"bank":0,"alignment":0,"align_offset":0,"fill":null,"size":3},
+{"name":"vars","kind":"WRAMX","modifier":"fragment","address":null,"bank":2,"alignment":4,"align_offset":2,"fill":null,"size":5}
+]
+"#
+        );
+    }
+
+    #[test]
+    fn fill_byte() {
+        let (diagnostics, sections) = assemble_with(
+            "fill.asm",
+            concat!(
+                "SECTION \"Fixed\", ROM0[$102], FILL[$FF]\ndb 1\nds align[3]\nds 1\n",
+                "SECTION \"Floating\", ROM0, ALIGN[2, 1]\ndb 2\nds align[4, 3]\n",
+                "SECTION \"Bad\", ROM0, FILL[256]\n",
+                "SECTION \"Worse\", ROM0, FILL, KEEP[1]\n",
+                // `FILL` is only special as a section attribute.
+                "SECTION \"Label\", ROM0\nFill: dw Fill\n",
+            ),
+            |_, options| options.pad_byte = 0x42,
+            |sections| {
+                ["Fixed", "Floating"].map(|name| {
+                    let section = sections.get_by_name(name).unwrap();
+                    (section.data().to_vec(), section.alignment(), section.fill())
+                })
+            },
+        );
+        assert_eq!(
+            diagnostics,
+            r#"error: Fill byte must be between 0 and 255 (inclusive), not 256
+  ┌─ fill.asm:8:27
+  │
+8 │ SECTION "Bad", ROM0, FILL[256]
+  │                           ^^^
+
+error: Section attribute "FILL" requires an argument, e.g. "FILL[...]"
+  ┌─ fill.asm:9:24
+  │
+9 │ SECTION "Worse", ROM0, FILL, KEEP[1]
+  │                        ^^^^
+
+error: Section attribute "KEEP" does not take an argument
+  ┌─ fill.asm:9:30
+  │
+9 │ SECTION "Worse", ROM0, FILL, KEEP[1]
+  │                              ^^^^
+
+"#
+        );
+        assert_eq!(
+            sections,
+            [
+                (
+                    vec![1, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+                    (0, 0),
+                    Some(0xFF)
+                ),
+                (vec![2, 0x42], (4, 1), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn iter_sections() {
+        let (diagnostics, sections) = assemble_with(
+            "iter.asm",
+            "SECTION \"code\", ROM0\ndw Label\nSECTION \"vars\", WRAM0[$C000]\nLabel: ds 3\n",
+            |_, _| {},
+            |sections| {
+                sections
+                    .iter()
+                    .map(|(name, section)| {
+                        (
+                            name.to_string(),
+                            section.kind(),
+                            section.address(),
+                            section.len(),
+                            section.patches().collect::<Vec<_>>(),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            },
+        );
+        assert_eq!(diagnostics, "");
+        assert_eq!(
+            sections,
+            [
+                (
+                    "code".to_string(),
+                    Kind::Rom0,
+                    None,
+                    2,
+                    vec![(0, RelocKind::Word)]
+                ),
+                ("vars".to_string(), Kind::Wram0, Some(0xC000), 3, vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn lookup_by_name() {
+        let (diagnostics, (kind, missing)) = assemble_with(
+            "lookup.asm",
+            "SECTION \"vars\", HRAM\nds 4\n",
+            |_, _| {},
+            |sections| {
+                (
+                    sections.get_by_name("vars").map(|section| section.kind()),
+                    sections.get_by_name("code").is_none(),
+                )
+            },
+        );
+        assert_eq!(diagnostics, "");
+        assert_eq!(kind, Some(Kind::Hram));
+        assert!(missing);
+    }
+
+    #[test]
+    fn lookup_by_name_mut() {
+        let mut sections = Sections::new();
+        let attrs = NormalizedSectAttrs {
+            address: None,
+            bank: None,
+            alignment: 0,
+            align_offset: 0,
+            keep: false,
+            fill: None,
+        };
+        sections
+            .add_section(
+                "vars".into(),
+                Kind::Hram,
+                Modifier::Normal,
+                attrs,
+                Location::builtin(),
+                Location::builtin(),
+            )
+            .unwrap();
+
+        assert_eq!(
+            sections
+                .get_by_name_mut("vars")
+                .map(|section| section.kind()),
+            Some(Kind::Hram)
+        );
+        assert!(sections.get_by_name_mut("code").is_none());
+    }
+
+    #[test]
+    fn section_overflow() {
+        let errors = |source| assemble("overflow.asm", source);
+
+        assert_eq!(
+            error_lines(&errors("SECTION \"end\", ROMX[$7FFC]\ndb \"hello\"\ndb 0\n")),
+            ["error: Byte 4 of the 5 output here overflows the section, which can only hold 4 bytes"]
+        );
+        assert_eq!(
+            error_lines(&errors("SECTION \"vars\", HRAM[$FFFC]\nds 3\n")),
+            [] as [&str; 0]
+        );
+        assert_eq!(
+            error_lines(&errors("SECTION \"vars\", HRAM[$FFFC]\nds 2\nds 2\n")),
+            ["error: Byte 1 of the 2 output here overflows the section, which can only hold 3 bytes"]
+        );
+    }
+
+    #[test]
+    fn incbin() {
+        let file = std::env::temp_dir().join(format!("rgbasm-incbin-{}.bin", std::process::id()));
+        let contents: Vec<u8> = (0..0x100000).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&file, &contents).unwrap();
+        let path = file.display();
+
+        let (diagnostics, data) = assemble_with(
+            "incbin.asm",
+            &format!(
+                "SECTION \"a\", ROMX\nINCBIN \"{path}\", $80000, $100\nINCBIN \"{path}\", $FFFFE\nINCBIN \"{path}\", $100000\n"
+            ),
+            |_, _| {},
+            |sections| sections.active_section().unwrap().data().to_vec(),
+        );
+        assert_eq!(diagnostics, "");
+        assert_eq!(data[..0x100], contents[0x80000..0x80100]);
+        assert_eq!(data[0x100..], contents[0xFFFFE..]);
+
+        let diagnostics = assemble(
+            "incbin.asm",
+            &format!(
+                "SECTION \"a\", ROMX\nINCBIN \"{path}\", $100001\nINCBIN \"{path}\", $FFFFF, 2\n"
+            ),
+        );
+        assert_eq!(
+            error_lines(&diagnostics),
+            [
+                "error: `INCBIN` starts at byte 1048577, past the end of the 1048576-byte file",
+                "error: `INCBIN` of 2 bytes from byte 1048575 goes past the end of the 1048576-byte file",
+            ]
+        );
+
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn full_bank_without_realloc() {
+        let (diagnostics, (len, capacity)) = assemble_with(
+            "full.asm",
+            &format!(
+                "SECTION \"full\", ROMX\n{}",
+                "\tld bc, $1234\n\tnop\n".repeat(0x1000)
+            ),
+            |_, _| {},
+            |sections| {
+                let data = &sections.get_by_name("full").unwrap().data;
+                (data.len(), data.capacity())
+            },
+        );
+        assert_eq!(diagnostics, "");
+        assert_eq!(len, 0x4000);
+        // The section was allocated once and for all.
+        assert_eq!(capacity, 0x4000);
+    }
+
+    #[test]
+    fn jr_cross_section() {
+        let (diagnostics, (data, nb_patches)) = assemble_with(
+            "jr.asm",
+            "SECTION \"a\", ROM0\nTarget:\n\tnop\nSECTION \"b\", ROM0\nLocal:\n\tjr Local\n\tjr nz, Target\n",
+            |_, _| {},
+            |sections| {
+                let section = sections.active_section().unwrap();
+                (section.data().to_vec(), section.nb_patches())
+            },
+        );
+        // Both jumps are left for the linker to range-check.
+        assert_eq!(data, [0x18, 0x00, 0x20, 0x00]);
+        assert_eq!(nb_patches, 2);
+        assert_eq!(
+            diagnostics,
+            r#"warning[-Wjr-cross-section]: `jr` target "Target" is in a different section
+  ┌─ jr.asm:7:9
+  │
+7 │         jr nz, Target
+  │                ^^^^^^
+  │
+  = Whether it is within range can only be checked by the linker
+
+"#
+        );
+    }
+
+    #[test]
+    fn huge_reservation() {
+        let mut sections = Sections::new();
+        let attrs = NormalizedSectAttrs {
+            address: None,
+            bank: None,
+            alignment: 0,
+            align_offset: 0,
+            keep: false,
+            fill: None,
+        };
+        sections
+            .add_section(
+                "a".into(),
+                Kind::Wram0,
+                Modifier::Normal,
+                attrs,
+                Location::builtin(),
+                Location::builtin(),
+            )
+            .unwrap();
+        let mut section = sections.active_section_mut().unwrap();
+
+        assert!(matches!(
+            section.reserve(usize::MAX - 1, 0),
+            Err(AsmErrorKind::SectionOverflow(..))
+        ));
+        // Further overflow is normally not reported again, but the size cannot grow any more.
+        assert!(matches!(section.reserve(1, 0), Ok(())));
+        assert!(matches!(
+            section.reserve(2, 0),
+            Err(AsmErrorKind::SectionSizeOverflow(2))
+        ));
     }
 }
@@ -2,18 +2,35 @@ use std::{collections::HashMap, rc::Rc};
 
 use string_interner::{backend::StringBackend, symbol::SymbolU32, StringInterner, Symbol};
 
-use crate::{
+use crate::asm::{
+    expr::Expression,
     fstack::Fstack,
     input::SourceString,
     language::{AsmError, AsmErrorKind, Location, SymEvalErrKind},
     macro_args::MacroArgs,
-    sections::Sections,
+    sections::{SectionId, Sections},
 };
 
 #[derive(Debug)]
 pub struct Symbols<'fstack> {
     names: StringInterner<StringBackend<SymbolU32>>,
     symbols: HashMap<SymbolU32, SymbolData<'fstack>>,
+    /// Constants whose value refers to symbols that are not defined yet, in definition order.
+    pending: Vec<PendingConstant<'fstack>>,
+}
+
+#[derive(Debug)]
+struct PendingConstant<'fstack> {
+    name_begin: Location<'fstack>,
+    name_string: SourceString,
+    name_end: Location<'fstack>,
+    expr: Expression<'fstack>,
+}
+
+impl Default for Symbols<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<'fstack> Symbols<'fstack> {
@@ -36,12 +53,17 @@ impl<'fstack> Symbols<'fstack> {
                         is_builtin: true,
                         definition: (Location::builtin(), Location::builtin()),
                         is_referenced: false,
+                        is_exported: false,
                     },
                 )
             })
             .collect();
 
-        Self { names, symbols }
+        Self {
+            names,
+            symbols,
+            pending: vec![],
+        }
     }
 
     fn def_non_reloc(
@@ -51,6 +73,7 @@ impl<'fstack> Symbols<'fstack> {
         name_end: Location<'fstack>,
         kind: SymbolKind,
         allow_redef: bool,
+        is_exported: bool,
     ) -> Result<(), AsmError<'fstack>> {
         let name = self.names.get_or_intern(&name_string);
         match self.symbols.get_mut(&name) {
@@ -63,6 +86,7 @@ impl<'fstack> Symbols<'fstack> {
                         is_builtin: false,
                         definition: (name_begin, name_end),
                         is_referenced: false,
+                        is_exported,
                     },
                 );
                 Ok(())
@@ -79,6 +103,7 @@ impl<'fstack> Symbols<'fstack> {
                     debug_assert!(!other.is_builtin);
                     // Additionally, inherit the "referenced" state.
                     other.kind = kind;
+                    other.is_exported |= is_exported;
                     other.definition = (name_begin, name_end); // Forget about the previous definition.
                     Ok(())
                 } else {
@@ -108,9 +133,160 @@ impl<'fstack> Symbols<'fstack> {
             name_end,
             SymbolKind::Constant(value),
             allow_redef,
+            false,
         )
     }
 
+    /// Defines a constant with `EQU`. If its value refers to symbols that are not defined yet, the
+    /// definition is held back until they all are, with everything else folded in right away;
+    /// this returns the errors of any definition that went through, including held back ones that
+    /// this one unblocked.
+    pub fn def_equ(
+        &mut self,
+        name_begin: Location<'fstack>,
+        name_string: SourceString,
+        name_end: Location<'fstack>,
+        expr: Expression<'fstack>,
+        macro_args: Option<&MacroArgs>,
+        sections: &Sections,
+    ) -> Vec<AsmError<'fstack>> {
+        let constant = PendingConstant {
+            name_begin,
+            name_string,
+            name_end,
+            expr: expr.fold_known_symbols(self, macro_args, sections),
+        };
+        // Only symbols that are yet to be defined are worth waiting for; if a defined one is still
+        // unknown (e.g. a label in a floating section), waiting would not help.
+        if constant.expr.references().next().is_some()
+            && constant.expr.references().all(|id| !self.is_defined(id))
+        {
+            self.pending.push(constant);
+            return vec![];
+        }
+
+        match self.def_evaluated(constant, macro_args, sections) {
+            Ok(()) => self.resolve_pending(sections),
+            Err(error) => vec![error],
+        }
+    }
+
+    /// Defines the held back constants that can now be, which may in turn unblock others.
+    /// This must be called after any symbol definition, and returns the errors of those constants.
+    pub fn resolve_pending(&mut self, sections: &Sections) -> Vec<AsmError<'fstack>> {
+        let mut errors = vec![];
+        while let Some(idx) = self
+            .pending
+            .iter()
+            .position(|pending| pending.expr.references().all(|id| self.is_defined(id)))
+        {
+            let constant = self.pending.remove(idx);
+            // Macro args are long gone by now.
+            if let Err(error) = self.def_evaluated(constant, None, sections) {
+                errors.push(error);
+            }
+        }
+        errors
+    }
+
+    fn def_evaluated(
+        &mut self,
+        constant: PendingConstant<'fstack>,
+        macro_args: Option<&MacroArgs>,
+        sections: &Sections,
+    ) -> Result<(), AsmError<'fstack>> {
+        let (value, _, _) = constant.expr.try_eval(self, macro_args, sections)?;
+        self.def_constant(
+            constant.name_begin,
+            constant.name_string,
+            constant.name_end,
+            value,
+            false,
+        )
+    }
+
+    /// Reports the constants that could never be defined: either they are defined in terms of
+    /// each other, or they refer to something that is not a constant. Any that can be computed
+    /// after all is defined.
+    pub fn end_of_input(&mut self, sections: &Sections) -> Vec<AsmError<'fstack>> {
+        let pending = std::mem::take(&mut self.pending);
+        let indices: HashMap<&str, usize> = pending
+            .iter()
+            .enumerate()
+            .map(|(idx, pending)| (&*pending.name_string, idx))
+            .collect();
+        let deps: Vec<Vec<usize>> = pending
+            .iter()
+            .map(|pending| {
+                pending
+                    .expr
+                    .references()
+                    .filter_map(|id| indices.get(self.name_of(id)).copied())
+                    .collect()
+            })
+            .collect();
+
+        // Look for cycles depth-first, starting from each constant in definition order.
+        let mut in_cycle = vec![false; pending.len()];
+        let mut visited = vec![false; pending.len()];
+        let mut errors = vec![];
+        for start in 0..pending.len() {
+            let mut path = vec![(start, 0)];
+            while let Some((node, next_dep)) = path.last_mut() {
+                let node = *node;
+                visited[node] = true;
+                let Some(&dep) = deps[node].get(*next_dep) else {
+                    path.pop();
+                    continue;
+                };
+                *next_dep += 1;
+
+                if let Some(pos) = path.iter().position(|&(member, _)| member == dep) {
+                    let cycle = &path[pos..];
+                    let mut description = String::new();
+                    for &(member, _) in cycle {
+                        in_cycle[member] = true;
+                        description.push_str(&pending[member].name_string);
+                        description.push_str(" -> ");
+                    }
+                    description.push_str(&pending[dep].name_string);
+                    let first = &pending[dep];
+                    errors.push(AsmError {
+                        begin: first.name_begin.clone(),
+                        end: first.name_end.clone(),
+                        kind: AsmErrorKind::EquCycle(first.name_string.clone(), description),
+                    });
+                } else if !visited[dep] {
+                    path.push((dep, 0));
+                }
+            }
+        }
+
+        for (pending, in_cycle) in pending.into_iter().zip(in_cycle) {
+            if !in_cycle {
+                if let Err(error) = self.def_evaluated(pending, None, sections) {
+                    errors.push(error);
+                }
+            }
+        }
+        errors
+    }
+
+    /// Whether the symbol with the given ID has been defined, as opposed to only referenced.
+    fn is_defined(&self, id: u32) -> bool {
+        let name = SymbolU32::try_from_usize(id as usize).unwrap();
+        self.symbols
+            .get(&name)
+            .is_some_and(|symbol| !matches!(symbol.kind, SymbolKind::NumRef))
+    }
+
+    fn name_of(&self, id: u32) -> &str {
+        let name = SymbolU32::try_from_usize(id as usize).unwrap();
+        self.names
+            .resolve(name)
+            .expect("Generated invalid sym ID in RPN!?")
+    }
+
     pub fn def_variable(
         &mut self,
         name_begin: Location<'fstack>,
@@ -124,6 +300,41 @@ impl<'fstack> Symbols<'fstack> {
             name_end,
             SymbolKind::Variable(value),
             true,
+            false,
+        )
+    }
+
+    /// Steps a `FOR` loop's variable to its next value. The variable is left alone if the loop's
+    /// body redefined it as something else.
+    pub fn set_for_var(&mut self, name_str: &str, value: i32) {
+        let Some(name) = self.names.get(name_str) else {
+            return;
+        };
+        if let Some(SymbolData {
+            kind: SymbolKind::Variable(old_value),
+            ..
+        }) = self.symbols.get_mut(&name)
+        {
+            *old_value = value;
+        }
+    }
+
+    pub fn def_label(
+        &mut self,
+        name_begin: Location<'fstack>,
+        name_string: SourceString,
+        name_end: Location<'fstack>,
+        section: SectionId,
+        offset: u16,
+        is_exported: bool,
+    ) -> Result<(), AsmError<'fstack>> {
+        self.def_non_reloc(
+            name_begin,
+            name_string,
+            name_end,
+            SymbolKind::Label { section, offset },
+            false,
+            is_exported,
         )
     }
 
@@ -140,6 +351,7 @@ impl<'fstack> Symbols<'fstack> {
             name_end,
             SymbolKind::String(string),
             false,
+            false,
         )
     }
 
@@ -156,6 +368,7 @@ impl<'fstack> Symbols<'fstack> {
             name_end,
             SymbolKind::Macro(body),
             false,
+            false,
         )
     }
 
@@ -190,6 +403,20 @@ impl<'fstack> Symbols<'fstack> {
         }
     }
 
+    /// If the symbol with the given ID is a label, returns its name and the section it belongs to.
+    pub fn get_label_section(&self, id: u32) -> Option<(&str, SectionId)> {
+        let name = SymbolU32::try_from_usize(id as usize).unwrap();
+        match self.symbols.get(&name)?.kind {
+            SymbolKind::Label { section, .. } => Some((
+                self.names
+                    .resolve(name)
+                    .expect("Generated invalid sym ID in RPN!?"),
+                section,
+            )),
+            _ => None,
+        }
+    }
+
     pub fn get_string(&self, name_str: &SourceString) -> Result<&Rc<SourceString>, AsmErrorKind> {
         self.names
             .get(name_str)
@@ -253,6 +480,7 @@ impl<'fstack> Symbols<'fstack> {
                     is_builtin: false,
                     definition: (begin.clone(), end.clone()),
                     is_referenced: true,
+                    is_exported: false,
                 });
             }
             Entry::Occupied(mut entry) => {
@@ -265,6 +493,19 @@ impl<'fstack> Symbols<'fstack> {
         }
         Ok(name.to_usize() as u32) // This cast can't truncate, because the symbol is internally 32-bit.
     }
+
+    /// Iterates over the labels that were neither referenced nor exported, in definition order.
+    pub fn unused_labels(
+        &self,
+    ) -> impl Iterator<Item = (&str, &(Location<'fstack>, Location<'fstack>))> {
+        self.names.into_iter().filter_map(|(name, string)| {
+            let symbol = self.symbols.get(&name)?;
+            (matches!(symbol.kind, SymbolKind::Label { .. })
+                && !symbol.is_referenced
+                && !symbol.is_exported)
+                .then_some((string, &symbol.definition))
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -273,6 +514,7 @@ pub struct SymbolData<'fstack> {
     is_builtin: bool,
     definition: (Location<'fstack>, Location<'fstack>),
     is_referenced: bool,
+    is_exported: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -280,7 +522,7 @@ pub enum SymbolKind {
     Constant(i32),
     Variable(i32),
     Label {
-        section: (),
+        section: SectionId,
         offset: u16,
     },
     /// Empty reference, but only numeric types allow that.
@@ -302,7 +544,10 @@ impl SymbolData<'_> {
     ) -> Result<i32, SymEvalErrKind> {
         match &self.kind {
             SymbolKind::Constant(value) | SymbolKind::Variable(value) => Ok(*value),
-            SymbolKind::Label { section, offset } => todo!(),
+            SymbolKind::Label { section, offset } => match sections.try_get_addr(*section) {
+                Some(addr) => Ok(addr.wrapping_add(*offset).into()),
+                None => Err(SymEvalErrKind::NonConst(SourceString::clone(name))),
+            },
             SymbolKind::Pc => match sections
                 .active_section()
                 .ok_or_else(|| SymEvalErrKind::PcOutsideSection)?
@@ -367,3 +612,151 @@ impl SymbolKind {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::{
+        error::WarningState,
+        language::WarningId,
+        test_utils::{assemble, assemble_with, error_lines},
+    };
+
+    fn assemble_unused(source: &str) -> String {
+        assemble_with(
+            "unused.asm",
+            source,
+            |reporter, _| reporter.set_warning_state(WarningId::Unused, WarningState::Enabled),
+            |_| (),
+        )
+        .0
+    }
+
+    #[test]
+    fn unused_labels() {
+        assert_eq!(
+            assemble_unused("SECTION \"a\", ROM0\nUnused:\n\tnop\n"),
+            r#"warning[-Wunused]: Label "Unused" is never referenced
+  ┌─ unused.asm:2:1
+  │
+2 │ Unused:
+  │ ^^^^^^
+  │
+  = If it is meant to be used by other files, export it with `::`
+
+"#
+        );
+        assert_eq!(
+            assemble_unused("SECTION \"a\", ROM0\nLoop:\n\tjr Loop\n\tjp Later\nLater:\n"),
+            ""
+        );
+        assert_eq!(
+            assemble_unused("SECTION \"a\", ROM0\nExported::\n\tnop\n"),
+            ""
+        );
+    }
+
+    #[test]
+    fn forward_equ() {
+        let (diagnostics, data) = assemble_with(
+            "equ.asm",
+            concat!(
+                "DEF Total EQU Double + Base\n",
+                "DEF Double EQU Base * 2\n",
+                "DEF Base EQU 3\n",
+                "SECTION \"a\", ROM0\n",
+                // `ds` needs its length right away.
+                "ds Total, \"total\"\n",
+                "ds Double, \"double\"\n",
+            ),
+            |_, _| {},
+            |sections| sections.active_section().unwrap().data().to_vec(),
+        );
+        assert_eq!(diagnostics, "");
+        assert_eq!(data, b"total\0\0\0\0double");
+    }
+
+    #[test]
+    fn forward_equ_context() {
+        let (diagnostics, data) = assemble_with(
+            "equ.asm",
+            concat!(
+                "DEF Start EQU Lbl\n",
+                "SECTION \"a\", ROM0[$100]\n",
+                // `@` must be taken where the constant is defined, not where it gets resolved.
+                "DEF Here EQU Later + @\n",
+                "ds 2, \"ab\"\n",
+                "DEF Later EQU 0\n",
+                "Lbl:\n",
+                "ds Here - $FF, \"h\"\n",
+                "ds Start - $100, \"s\"\n",
+            ),
+            |_, _| {},
+            |sections| sections.active_section().unwrap().data().to_vec(),
+        );
+        assert_eq!(diagnostics, "");
+        assert_eq!(data, b"abhs\0");
+    }
+
+    #[test]
+    fn equ_cycle() {
+        assert_eq!(
+            assemble(
+                "cycle.asm",
+                "DEF Ping EQU Pong + 1\nDEF Pong EQU Ping - 1\nDEF Loop EQU Loop\n",
+            ),
+            r#"error: Constant "Ping" is defined in terms of itself (Ping -> Pong -> Ping)
+  ┌─ cycle.asm:1:5
+  │
+1 │ DEF Ping EQU Pong + 1
+  │     ^^^^
+
+error: Constant "Loop" is defined in terms of itself (Loop -> Loop)
+  ┌─ cycle.asm:3:5
+  │
+3 │ DEF Loop EQU Loop
+  │     ^^^^
+
+"#
+        );
+    }
+
+    #[test]
+    fn macros() {
+        let (diagnostics, data) = assemble_with(
+            "macro.asm",
+            "MACRO twice\n\tdb \\1, \\1\nENDM\nSECTION \"a\", ROM0\nLabel:\n\ttwice 5\n\ttwice $42\n",
+            |_, _| {},
+            |sections| sections.active_section().unwrap().data().to_vec(),
+        );
+        assert_eq!(diagnostics, "");
+        assert_eq!(data, [5, 5, 0x42, 0x42]);
+
+        let diagnostics = assemble(
+            "macro.asm",
+            "ENDM\nMACRO m\nENDM\nMACRO m\nENDM\nPURGE m\nMACRO m\nENDM\n",
+        );
+        assert_eq!(
+            error_lines(&diagnostics),
+            [
+                "error: `ENDM` found outside of a macro definition",
+                "error: m is already defined",
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_macro() {
+        assert_eq!(
+            assemble("unterminated.asm", "MACRO m\n\tnop\n"),
+            r#"error: Unterminated macro definition
+  ┌─ unterminated.asm:1:7
+  │
+1 │ MACRO m
+  │       ^
+  │
+  = The file ended before the matching `ENDM`
+
+"#
+        );
+    }
+}
@@ -0,0 +1,59 @@
+//! Helpers for tests that need to run the whole assembler.
+
+use std::{cell::RefCell, rc::Rc};
+
+use codespan_reporting::term::termcolor::NoColor;
+
+use crate::asm::{
+    assemble::{run, SharedBuf},
+    error::Reporter,
+    options::Options,
+    sections::Sections,
+    symbols::Symbols,
+    Fstack, Storage,
+};
+
+/// Keeps only the first line of each error in `diagnostics`, i.e. without any source snippets.
+pub fn error_lines(diagnostics: &str) -> Vec<&str> {
+    diagnostics
+        .lines()
+        .filter(|line| line.starts_with("error"))
+        .collect()
+}
+
+/// Assembles `source` with the default settings, and returns the diagnostics it produced.
+pub fn assemble(name: &str, source: &str) -> String {
+    assemble_with(name, source, |_, _| {}, |_| ()).0
+}
+
+/// Assembles `source` after letting `setup` tweak the settings, and returns the diagnostics it
+/// produced alongside what `inspect` extracted from the resulting sections.
+pub fn assemble_with<S, I, T>(name: &str, source: &str, setup: S, inspect: I) -> (String, T)
+where
+    S: FnOnce(&mut Reporter, &mut Options),
+    I: FnOnce(&Sections) -> T,
+{
+    let buf = SharedBuf::default();
+    let mut reporter = Reporter::with_writer(Box::new(NoColor::new(buf.clone())));
+    let mut options = Options::default();
+    setup(&mut reporter, &mut options);
+    let reporter = RefCell::new(reporter);
+    let options = RefCell::new(options);
+
+    let root_file = Storage::from_readable(name.to_string().into(), source.as_bytes())
+        .expect("Reading from a slice cannot fail");
+    let fstack = Fstack::new(Rc::new(root_file), vec![]);
+    let sections = RefCell::new(Sections::new());
+    let symbols = RefCell::new(Symbols::new());
+    run(
+        &fstack,
+        &reporter,
+        &options,
+        &sections,
+        &symbols,
+        &RefCell::new(std::io::sink()),
+    );
+    let inspected = inspect(&sections.borrow());
+
+    (buf.contents(), inspected)
+}
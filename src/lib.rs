@@ -1,3 +1,6 @@
+use std::ops::RangeInclusive;
+
+pub mod asm;
 pub mod rpn;
 pub mod section;
 
@@ -30,6 +33,16 @@ impl RelocKind {
         }
     }
 
+    /// The values that can be stored without any truncation warning.
+    pub fn range(self) -> RangeInclusive<i32> {
+        match self {
+            Self::Byte => -0x80..=0xFF,
+            Self::Word => -0x8000..=0xFFFF,
+            Self::Long => i32::MIN..=i32::MAX,
+            Self::Ofs8 => -0x80..=0x7F,
+        }
+    }
+
     pub fn is_in_range(&self, value: i32) -> TruncationLevel {
         let nb_bits = match self {
             Self::Byte => 8,
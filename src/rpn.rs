@@ -3,7 +3,7 @@
 use parse_display::Display;
 use try_from_discrim::TryFrom;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Rpn(Vec<u8>);
 
 impl Rpn {
@@ -29,6 +29,82 @@ impl Rpn {
         ])
     }
 
+    pub fn bank_sym(id: u32) -> Self {
+        let bytes = id.to_le_bytes();
+        Self(vec![
+            Command::BankSym as u8,
+            bytes[0],
+            bytes[1],
+            bytes[2],
+            bytes[3],
+        ])
+    }
+
+    pub fn sizeof_sect(name: &str) -> Self {
+        Self::with_sect_name(Command::SizeofSect, name)
+    }
+
+    pub fn startof_sect(name: &str) -> Self {
+        Self::with_sect_name(Command::StartofSect, name)
+    }
+
+    fn with_sect_name(command: Command, name: &str) -> Self {
+        let mut bytes = Vec::with_capacity(name.len() + 2);
+        bytes.push(command as u8);
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.push(0);
+        Self(bytes)
+    }
+
+    /// Enumerates the IDs of all symbols that the expression depends on, in order of appearance.
+    /// Symbols are reported once per reference, so they may be returned several times.
+    pub fn references(&self) -> References<'_> {
+        References(self.0.iter())
+    }
+
+    /// Replaces the symbols whose value is already known (per `get_value`) with constants.
+    /// `BANK` references are left alone, and the expression is not simplified any further.
+    pub fn fold_symbols<F: FnMut(u32) -> Option<i32>>(&self, mut get_value: F) -> Self {
+        let mut folded = Vec::with_capacity(self.0.len());
+        let mut bytes = self.0.iter().copied();
+        while let Some(command) = bytes.next() {
+            match Command::try_from(command).expect("Unknown RPN command!?") {
+                Command::Symbol => {
+                    let mut id = [0; 4];
+                    for byte in &mut id {
+                        *byte = bytes.next().unwrap();
+                    }
+                    match get_value(u32::from_le_bytes(id)) {
+                        Some(value) => {
+                            folded.push(Command::Constant as u8);
+                            folded.extend_from_slice(&value.to_le_bytes());
+                        }
+                        None => {
+                            folded.push(command);
+                            folded.extend_from_slice(&id);
+                        }
+                    }
+                }
+                Command::Constant | Command::BankSym => {
+                    folded.push(command);
+                    folded.extend(bytes.by_ref().take(4));
+                }
+                // These are followed by a NUL-terminated section name.
+                Command::BankSect | Command::SizeofSect | Command::StartofSect => {
+                    folded.push(command);
+                    for byte in bytes.by_ref() {
+                        folded.push(byte);
+                        if byte == 0 {
+                            break;
+                        }
+                    }
+                }
+                _ => folded.push(command),
+            }
+        }
+        Self(folded)
+    }
+
     pub fn try_get_constant(&self) -> Option<i32> {
         if self.0.len() == 5 && self.0[0] == Command::Constant as _ {
             let mut bytes = [0; 4];
@@ -116,77 +192,68 @@ impl Rpn {
         let lhs_constant = lhs.try_get_constant();
         let mut rpn = lhs.0;
 
-        if let (Command::LogicAnd, Some(constant)) = (operator, lhs_constant) {
-            if constant == 0 {
-                // Short-circuit evaluation means the value remains zero, and we ignore any RHS errors.
-            } else {
-                // We know the LHS is true, so the expression is equivalent to the RHS.
-                // Let's simplify by reusing the expression directly.
-                rpn = rhs?.0;
-            }
-        } else if let (Command::LogicOr, Some(constant)) = (operator, lhs_constant) {
-            if constant == 0 {
-                // We know the LHS is false, so the expression is equivalent to the RHS.
-                // Let's simplify by reusing the expression directly.
-                rpn = rhs?.0;
-            } else if constant != 1 {
-                // Short-circuit evaluation means the value becomes 1, and we ignore any RHS errors.
-                // If the value is not 1, we must set it to that, though.
+        // Short-circuit evaluation: `0 && x` and `1 || x` ignore `x`, even if it failed to evaluate.
+        match (operator, lhs_constant) {
+            (Command::LogicAnd, Some(0)) => return Ok(Self(rpn)), // The value remains zero.
+            (Command::LogicOr, Some(constant)) if constant != 0 => {
+                // The value becomes 1, which it may not already be.
                 debug_assert_eq!(rpn.len(), 5);
                 debug_assert_eq!(rpn[0], Command::Constant as _);
                 rpn[1..5].copy_from_slice(&1i32.to_le_bytes());
+                return Ok(Self(rpn));
             }
-        } else {
-            let rhs = rhs?; // If this is an error, it cannot be ignored anymore.
+            _ => {}
+        }
 
-            let constants = (lhs_constant, rhs.try_get_constant());
-            if let (Some(lhs), Some(rhs)) = constants {
-                debug_assert_eq!(rpn.len(), 5);
-                debug_assert_eq!(rpn[0], Command::Constant as _);
+        let rhs = rhs?; // If this is an error, it cannot be ignored anymore.
 
-                let bytes = match operator {
-                    Command::Add => lhs.wrapping_add(rhs),
-                    Command::Sub => lhs.wrapping_sub(rhs),
-                    Command::Mul => lhs.wrapping_mul(rhs),
-                    Command::Div => div_floor(lhs, rhs)?.0,
-                    Command::Mod => div_floor(lhs, rhs)?.1,
-                    Command::Exponent => lhs.pow(rhs as u32),
-                    Command::BitOr => lhs | rhs,
-                    Command::BitAnd => lhs & rhs,
-                    Command::BitXor => lhs ^ rhs,
-                    Command::LogicAnd => unreachable!(), // Handled by prior short-circuiting code.
-                    Command::LogicOr => unreachable!(),  // Handled by prior short-circuiting code.
-                    Command::Eq => (lhs == rhs) as _,
-                    Command::Ne => (lhs != rhs) as _,
-                    Command::Gt => (lhs > rhs) as _,
-                    Command::Lt => (lhs < rhs) as _,
-                    Command::Gte => (lhs >= rhs) as _,
-                    Command::Lte => (lhs <= rhs) as _,
-                    Command::Shl => shl(lhs, rhs),
-                    Command::Shr => shr(lhs, rhs),
-                    Command::Ushr => ushr(lhs, rhs),
-
-                    // These are not binary operators.
-                    Command::Complement
-                    | Command::LogicNot
-                    | Command::Neg
-                    | Command::BankSym
-                    | Command::BankSect
-                    | Command::BankSelf
-                    | Command::SizeofSect
-                    | Command::StartofSect
-                    | Command::HighCheck
-                    | Command::RstCheck
-                    | Command::Constant
-                    | Command::Symbol => panic!("{operator:?} is not a binary operator!?"),
-                }
-                .to_le_bytes();
-                rpn[1..5].copy_from_slice(&bytes);
-            } else {
-                rpn.reserve(rhs.0.len() + 1);
-                rpn.extend_from_slice(&rhs.0);
-                rpn.push(operator as _);
+        let constants = (lhs_constant, rhs.try_get_constant());
+        if let (Some(lhs), Some(rhs)) = constants {
+            debug_assert_eq!(rpn.len(), 5);
+            debug_assert_eq!(rpn[0], Command::Constant as _);
+
+            let bytes = match operator {
+                Command::Add => lhs.wrapping_add(rhs),
+                Command::Sub => lhs.wrapping_sub(rhs),
+                Command::Mul => lhs.wrapping_mul(rhs),
+                Command::Div => div_floor(lhs, rhs)?.0,
+                Command::Mod => div_floor(lhs, rhs)?.1,
+                Command::Exponent => lhs.pow(rhs as u32),
+                Command::BitOr => lhs | rhs,
+                Command::BitAnd => lhs & rhs,
+                Command::BitXor => lhs ^ rhs,
+                Command::LogicAnd => (lhs != 0 && rhs != 0) as _,
+                Command::LogicOr => (lhs != 0 || rhs != 0) as _,
+                Command::Eq => (lhs == rhs) as _,
+                Command::Ne => (lhs != rhs) as _,
+                Command::Gt => (lhs > rhs) as _,
+                Command::Lt => (lhs < rhs) as _,
+                Command::Gte => (lhs >= rhs) as _,
+                Command::Lte => (lhs <= rhs) as _,
+                Command::Shl => shl(lhs, rhs),
+                Command::Shr => shr(lhs, rhs),
+                Command::Ushr => ushr(lhs, rhs),
+
+                // These are not binary operators.
+                Command::Complement
+                | Command::LogicNot
+                | Command::Neg
+                | Command::BankSym
+                | Command::BankSect
+                | Command::BankSelf
+                | Command::SizeofSect
+                | Command::StartofSect
+                | Command::HighCheck
+                | Command::RstCheck
+                | Command::Constant
+                | Command::Symbol => panic!("{operator:?} is not a binary operator!?"),
             }
+            .to_le_bytes();
+            rpn[1..5].copy_from_slice(&bytes);
+        } else {
+            rpn.reserve(rhs.0.len() + 1);
+            rpn.extend_from_slice(&rhs.0);
+            rpn.push(operator as _);
         }
 
         Ok(Self(rpn))
@@ -328,20 +395,19 @@ impl Rpn {
                 Command::Ushr => {
                     bin_op!(|lhs, rhs| Ok(ushr(lhs, rhs)));
                 }
+                // Had these been computable, they would have been folded when the expression was built.
                 Command::BankSym => {
-                    todo!();
+                    for _ in 0..4 {
+                        bytes.next().unwrap();
+                    }
+                    eval_stack.push(Err(EvalError::NotConstant));
                 }
-                Command::BankSect => {
-                    todo!();
+                Command::BankSect | Command::SizeofSect | Command::StartofSect => {
+                    while *bytes.next().unwrap() != 0 {}
+                    eval_stack.push(Err(EvalError::NotConstant));
                 }
                 Command::BankSelf => {
-                    todo!();
-                }
-                Command::SizeofSect => {
-                    todo!();
-                }
-                Command::StartofSect => {
-                    todo!();
+                    eval_stack.push(Err(EvalError::NotConstant));
                 }
                 Command::HighCheck => {
                     let slot = eval_stack.last_mut().ok_or(EvalError::EvalStackEmpty)?;
@@ -373,6 +439,62 @@ impl Rpn {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct References<'rpn>(std::slice::Iter<'rpn, u8>);
+
+impl Iterator for References<'_> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(command) = self.0.next() {
+            match Command::try_from(*command).expect("Unknown RPN command!?") {
+                Command::Symbol | Command::BankSym => {
+                    let mut id = [0; 4];
+                    for byte in &mut id {
+                        *byte = *self.0.next().unwrap();
+                    }
+                    return Some(u32::from_le_bytes(id));
+                }
+                Command::Constant => {
+                    self.0.nth(3).unwrap();
+                }
+                // These are followed by a NUL-terminated section name.
+                Command::BankSect | Command::SizeofSect | Command::StartofSect => {
+                    while *self.0.next().unwrap() != 0 {}
+                }
+
+                Command::Add
+                | Command::Sub
+                | Command::Mul
+                | Command::Div
+                | Command::Mod
+                | Command::Neg
+                | Command::Exponent
+                | Command::BitOr
+                | Command::BitAnd
+                | Command::BitXor
+                | Command::Complement
+                | Command::LogicAnd
+                | Command::LogicOr
+                | Command::LogicNot
+                | Command::Eq
+                | Command::Ne
+                | Command::Gt
+                | Command::Lt
+                | Command::Gte
+                | Command::Lte
+                | Command::Shl
+                | Command::Shr
+                | Command::Ushr
+                | Command::BankSelf
+                | Command::HighCheck
+                | Command::RstCheck => {}
+            }
+        }
+        None
+    }
+}
+
 fn div_floor<SymErr>(dividend: i32, divisor: i32) -> Result<(i32, i32), EvalError<SymErr>> {
     if divisor == 0 {
         return Err(EvalError::DivByZero);
@@ -526,7 +648,7 @@ mod tests {
 
             for dividend in i32::MIN..=i32::MAX {
                 let (quotient, remainder) =
-                    div_floor(dividend, divisor).expect("Division should succeed");
+                    div_floor::<()>(dividend, divisor).expect("Division should succeed");
                 assert_eq!(
                     quotient.wrapping_mul(divisor).wrapping_add(remainder),
                     dividend,
@@ -535,4 +657,51 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn references() {
+        // `Foo + BANK(Bar)`, with `Foo` and `Bar` having IDs 42 and 1337 respectively.
+        let rpn = Rpn::binary_op::<()>(Ok(Rpn::symbol(42)), Command::Add, Ok(Rpn::bank_sym(1337)))
+            .unwrap();
+        assert_eq!(rpn.references().collect::<Vec<_>>(), [42, 1337]);
+
+        assert_eq!(Rpn::constant(1337).references().next(), None);
+    }
+
+    #[test]
+    fn fold_symbols() {
+        // `(Foo + Bar) * BANK(Foo)`, with `Foo` known to be 3 but not `Bar`.
+        let sum = Rpn::binary_op::<()>(Ok(Rpn::symbol(42)), Command::Add, Ok(Rpn::symbol(1337)));
+        let rpn = Rpn::binary_op(sum, Command::Mul, Ok(Rpn::bank_sym(42))).unwrap();
+        let folded = rpn.fold_symbols(|id| (id == 42).then_some(3));
+        assert_eq!(folded.references().collect::<Vec<_>>(), [1337, 42]);
+        let folded = folded.fold_symbols(|_| Some(2));
+        assert_eq!(folded.references().collect::<Vec<_>>(), [42]);
+    }
+
+    #[test]
+    fn short_circuit() {
+        let div_by_zero =
+            Rpn::binary_op::<()>(Ok(Rpn::constant(1)), Command::Div, Ok(Rpn::constant(0)));
+        let rpn = Rpn::binary_op(Ok(Rpn::constant(0)), Command::LogicAnd, div_by_zero).unwrap();
+        assert_eq!(rpn.try_get_constant(), Some(0));
+
+        // `Undefined` has ID 42.
+        let rpn = Rpn::binary_op::<()>(Ok(Rpn::constant(1)), Command::LogicOr, Ok(Rpn::symbol(42)))
+            .unwrap();
+        assert_eq!(rpn.try_get_constant(), Some(1));
+
+        // The other operand matters otherwise, and the result is still a boolean.
+        let rpn = Rpn::binary_op::<()>(
+            Ok(Rpn::constant(1)),
+            Command::LogicAnd,
+            Ok(Rpn::constant(5)),
+        )
+        .unwrap();
+        assert_eq!(rpn.try_get_constant(), Some(1));
+        let rpn =
+            Rpn::binary_op::<()>(Ok(Rpn::symbol(42)), Command::LogicAnd, Ok(Rpn::constant(0)))
+                .unwrap();
+        assert_eq!(rpn.try_get_constant(), None);
+    }
 }
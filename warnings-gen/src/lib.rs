@@ -61,11 +61,57 @@ mod impls {
         }
 
         let vis = input.vis;
+
+        let mut meta_groups = Vec::new();
+        let mut meta_group_entries = Vec::new();
+        for warning in &warnings {
+            let WarningKind::Meta(members) = &warning.kind else {
+                continue;
+            };
+
+            let const_name = Ident::new(&screaming_snake_case(&warning.name), warning.name.span());
+            let doc = format!("The warnings enabled by `-W{}`.", KebabCase(&warning.name));
+            let members = if members.len() == 1 && members[0] == warning.name {
+                // A group that only lists itself stands for all warnings.
+                warning_ids.iter().map(|id| id.to_token_stream()).collect()
+            } else {
+                members
+                    .iter()
+                    .map(|member| {
+                        warning_ids
+                            .iter()
+                            .find(|id| member.unraw() == id.name())
+                            .map(ToTokens::to_token_stream)
+                            .ok_or_else(|| Error::new_spanned(member, "Unknown warning"))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+            };
+            meta_groups.push(quote! {
+                #[doc = #doc]
+                #vis const #const_name: &'static [Self] = &[ #( Self::#members, )* ];
+            });
+            meta_group_entries.push((KebabCase(&warning.name).to_string(), const_name));
+        }
+        let meta_group_names = meta_group_entries.iter().map(|(name, _)| name);
+        let meta_group_consts = meta_group_entries.iter().map(|(_, const_name)| const_name);
+
+        let mut parametric = Vec::new();
+        for warning in &warnings {
+            let WarningKind::Numeric { default, .. } = warning.kind else {
+                continue;
+            };
+            let name = KebabCase(&warning.name).to_string();
+            let levels = warning_ids
+                .iter()
+                .filter(|id| id.base_ident == &warning.name);
+            parametric.push(quote! { (#name, &[ #( Self::#levels, )* ], #default) });
+        }
+
         let nb_warnings = warning_ids.len();
         let id_flags = warning_ids.iter().map(|id| format!("`-W{id}`"));
         let defaults = warning_ids.iter().map(|id| id.default);
         let patterns = warning_ids.iter().map(WarningId::pat);
-        let id_strings = warning_ids.iter().map(|id| format!("{id}"));
+        let id_strings: Vec<_> = warning_ids.iter().map(|id| format!("{id}")).collect();
         Ok(quote! {
             #[derive(Debug, Clone, Copy)]
             #vis enum #id_enum_name { #(
@@ -77,6 +123,27 @@ mod impls {
                 #vis const NB_WARNINGS: usize = #nb_warnings;
 
                 #vis const DEFAULTS: [bool; Self::NB_WARNINGS] = [ #( #defaults, )* ];
+
+                #( #meta_groups )*
+
+                /// The meta groups, by their flag names.
+                #vis const META_GROUPS: &'static [(&'static str, &'static [Self])] = &[
+                    #( (#meta_group_names, Self::#meta_group_consts), )*
+                ];
+
+                /// The warnings that have levels, by their flag names: each has its levels in
+                /// increasing order, and the default level.
+                #vis const PARAMETRIC: &'static [(&'static str, &'static [Self], u8)] = &[
+                    #( #parametric, )*
+                ];
+
+                /// Looks up a single warning by its flag name; levels are selected with a `=N` suffix.
+                #vis fn from_name(name: &str) -> Option<Self> {
+                    match name {
+                        #( #id_strings => Some(Self::#warning_ids), )*
+                        _ => None,
+                    }
+                }
             }
 
             impl ::core::convert::From<& #input_name> for #id_enum_name {
@@ -168,6 +235,14 @@ mod impls {
     }
 
     impl WarningId<'_> {
+        /// The name of the `WarningId` variant, without any raw identifier prefix.
+        fn name(&self) -> String {
+            match self.ident_suffix {
+                None => self.base_ident.unraw().to_string(),
+                Some(suffix) => format!("{}{suffix}", self.base_ident.unraw()),
+            }
+        }
+
         fn pat(&self) -> TokenStream {
             let ident = self.base_ident;
             let pat_kind = self.pat_kind;
@@ -183,10 +258,12 @@ mod impls {
         }
     }
 
-    impl Display for WarningId<'_> {
+    /// Formats an identifier in "kebab-case".
+    struct KebabCase<'a>(&'a Ident);
+
+    impl Display for KebabCase<'_> {
         fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
-            let ident_name = format!("{}", self.base_ident);
-            // Convert the name to "kebab-case".
+            let ident_name = format!("{}", self.0);
             let mut ident_chars = ident_name.chars();
             f.write_char(
                 ident_chars
@@ -202,6 +279,20 @@ mod impls {
                     f.write_char(ch)?;
                 }
             }
+            Ok(())
+        }
+    }
+
+    fn screaming_snake_case(ident: &Ident) -> String {
+        KebabCase(ident)
+            .to_string()
+            .replace('-', "_")
+            .to_ascii_uppercase()
+    }
+
+    impl Display for WarningId<'_> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+            KebabCase(self.base_ident).fmt(f)?;
 
             if let Some(suffix) = self.ident_suffix {
                 write!(f, "={suffix}")